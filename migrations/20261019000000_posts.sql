@@ -0,0 +1,11 @@
+-- The posts table predates the migrations, fresh databases need it before the idempotency columns are added.
+CREATE TABLE IF NOT EXISTS posts
+(
+    post_id   SERIAL PRIMARY KEY,
+    lang      CHAR(2) NOT NULL,
+    msg_id    INT     NOT NULL,
+    reply_id  INT,
+    file_type INT,
+    file_id   TEXT,
+    text      TEXT
+);
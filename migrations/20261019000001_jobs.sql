@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS jobs
+(
+    job_id        SERIAL PRIMARY KEY,
+    source_chat   BYTEA       NOT NULL,
+    source_msg_id INT         NOT NULL,
+    lang          VARCHAR(8)  NOT NULL,
+    state         TEXT        NOT NULL DEFAULT 'pending'
+        CHECK (state IN ('pending', 'translating', 'sent', 'failed')),
+    attempts      INT         NOT NULL DEFAULT 0,
+    last_error    TEXT,
+    created_at    TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at    TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS jobs_state_idx ON jobs (state, job_id);
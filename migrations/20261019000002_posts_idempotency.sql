@@ -0,0 +1,7 @@
+ALTER TABLE posts
+    ADD COLUMN IF NOT EXISTS source_chat   BIGINT,
+    ADD COLUMN IF NOT EXISTS source_msg_id INT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS posts_source_idx ON posts (source_chat, source_msg_id, lang);
+
+CREATE UNIQUE INDEX IF NOT EXISTS jobs_source_idx ON jobs (source_chat, source_msg_id, lang);
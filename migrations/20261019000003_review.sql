@@ -0,0 +1,8 @@
+ALTER TABLE jobs
+    DROP CONSTRAINT IF EXISTS jobs_state_check,
+    ADD CONSTRAINT jobs_state_check
+        CHECK (state IN ('pending', 'translating', 'review', 'sent', 'rejected', 'failed')),
+    ADD COLUMN IF NOT EXISTS draft_msg_id INT,
+    ADD COLUMN IF NOT EXISTS draft_text   TEXT;
+
+CREATE INDEX IF NOT EXISTS jobs_draft_idx ON jobs (draft_msg_id) WHERE state = 'review';
@@ -0,0 +1,15 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE TABLE IF NOT EXISTS translation_memory
+(
+    tm_id      SERIAL PRIMARY KEY,
+    lang       VARCHAR(8)  NOT NULL,
+    source     TEXT        NOT NULL,
+    target     TEXT        NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (lang, source)
+);
+
+CREATE INDEX IF NOT EXISTS translation_memory_source_trgm_idx
+    ON translation_memory USING gin (source gin_trgm_ops);
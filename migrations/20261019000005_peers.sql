@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS peers
+(
+    chat_id    BIGINT PRIMARY KEY,
+    packed     BYTEA       NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
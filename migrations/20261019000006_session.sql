@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS telegram_session
+(
+    id         INT PRIMARY KEY DEFAULT 1 CHECK (id = 1),
+    data       BYTEA       NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,136 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use grammers_client::Client;
+use grammers_client::types::Message;
+use sqlx::PgPool;
+use tracing::info;
+
+use crate::config::Config;
+use crate::lang::find_language;
+use crate::{outbox, peers, SOURCE_CHANNEL};
+
+/// Which historical source posts to backfill, either by message id or by date.
+/// Both bounds are inclusive, a missing upper bound means "up to the newest post".
+#[derive(Debug, PartialEq)]
+pub enum BackfillRange {
+    Ids(i32, Option<i32>),
+    Dates(DateTime<Utc>, Option<DateTime<Utc>>),
+}
+
+impl BackfillRange {
+    fn is_after(&self, message: &Message) -> bool {
+        match self {
+            Self::Ids(_, Some(to)) => message.id() > *to,
+            Self::Dates(_, Some(to)) => message.date() > *to,
+            _ => false,
+        }
+    }
+
+    fn is_before(&self, message: &Message) -> bool {
+        match self {
+            Self::Ids(from, _) => message.id() < *from,
+            Self::Dates(from, _) => message.date() < *from,
+        }
+    }
+}
+
+fn parse_date(date: &str, end_of_day: bool) -> Result<DateTime<Utc>> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")?;
+    let time = if end_of_day { date.and_hms_opt(23, 59, 59) } else { date.and_hms_opt(0, 0, 0) };
+    Ok(time.expect("valid time of day").and_utc())
+}
+
+impl FromStr for BackfillRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s.split_once("..").unwrap_or((s, ""));
+        let to = Some(to).filter(|to| !to.is_empty());
+
+        if let Ok(from) = from.parse::<i32>() {
+            let to = to.map(str::parse::<i32>).transpose()?;
+            return Ok(Self::Ids(from, to));
+        }
+
+        let from = parse_date(from, false)?;
+        let to = to.map(|to| parse_date(to, true)).transpose()?;
+        Ok(Self::Dates(from, to))
+    }
+}
+
+/// `backfill <lang> <from>[..<to>] [<source chat>]`, where the bounds are source message ids or `YYYY-MM-DD` dates.
+/// The source chat defaults to our own channel.
+pub async fn run(client: &Client, db_pool: &PgPool, config: &Config, args: &[String]) -> Result<()> {
+    let (lang, range, source_chat) = match args {
+        [lang, range] => (lang, range, SOURCE_CHANNEL),
+        [lang, range, source_chat] => (lang, range, source_chat.parse::<i64>().context("Invalid source chat")?),
+        _ => bail!("Usage: backfill <lang> <from>[..<to>] [<source chat>]"),
+    };
+
+    let lang = find_language(lang).ok_or_else(|| anyhow!("Unknown language `{lang}`"))?;
+    let range = range.parse::<BackfillRange>().context("Invalid backfill range")?;
+    if config.route_for(source_chat).is_none() {
+        bail!("Source chat {source_chat} has no route");
+    }
+
+    let mut posts = Vec::new();
+    let mut history = client.iter_messages(peers::get(client, source_chat).await?);
+    while let Some(message) = history.next().await? {
+        if range.is_before(&message) {
+            break;
+        }
+        if !range.is_after(&message) {
+            posts.push(message);
+        }
+    }
+
+    info!("Backfilling {} posts into {}", posts.len(), lang.username);
+
+    let langs = [lang.lang_key.code()];
+    let mut job_ids = Vec::new();
+    for message in posts.iter().rev() {
+        job_ids.extend(outbox::enqueue_for(message, &langs, db_pool).await?);
+    }
+
+    if job_ids.len() < posts.len() {
+        info!("{} posts were queued for {} before, leaving them to the daemon", posts.len() - job_ids.len(), lang.username);
+    }
+
+    outbox::drain(&job_ids, client, db_pool, config).await
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_id_range() {
+        assert_eq!("1200..1350".parse::<BackfillRange>().unwrap(), BackfillRange::Ids(1200, Some(1350)));
+        assert_eq!("1200".parse::<BackfillRange>().unwrap(), BackfillRange::Ids(1200, None));
+        assert_eq!("1200..".parse::<BackfillRange>().unwrap(), BackfillRange::Ids(1200, None));
+    }
+
+    #[test]
+    fn test_parse_date_range() {
+        // Act
+        let range = "2024-01-01..2024-01-31".parse::<BackfillRange>().unwrap();
+
+        // Assert
+        assert_eq!(
+            range,
+            BackfillRange::Dates(
+                parse_date("2024-01-01", false).unwrap(),
+                Some(parse_date("2024-01-31", true).unwrap()),
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_range() {
+        assert!("yesterday".parse::<BackfillRange>().is_err());
+        assert!("1200..later".parse::<BackfillRange>().is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use grammers_client::Client;
+
+use crate::config::{Config, TranslationConfig};
+use crate::formatting::ASSETS;
+use crate::lang::target_languages;
+use crate::{glossary, peers, terms};
+
+/// Problems with the embedded assets and the glossary files of the target languages.
+fn asset_problems(config: &TranslationConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for lang in target_languages() {
+        let code = lang.lang_key.code();
+        match ASSETS.get_file(format!("{code}/flags.json")) {
+            None => problems.push(format!("res/{code}/flags.json is missing")),
+            Some(file) => if let Err(e) = serde_json::from_slice::<BTreeMap<String, String>>(file.contents()) {
+                problems.push(format!("res/{code}/flags.json is invalid: {e}"));
+            },
+        }
+        if let Err(e) = terms::load_terms(&lang.lang_key) {
+            problems.push(format!("res/{code}/terms.json is invalid: {e}"));
+        }
+        if let Err(e) = glossary::load_entries(lang, config) {
+            problems.push(format!("Glossary of {code} is invalid: {e}"));
+        }
+    }
+
+    problems
+}
+
+/// Reads the newest message of every known chat, which fails without access to it.
+async fn chat_problems(client: &Client, config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for chat_id in peers::known_chats(config) {
+        let access = peers::with_peer(client, chat_id, |chat| async move {
+            client.iter_messages(chat).limit(1).next().await?;
+            Ok(())
+        })
+            .await;
+
+        match access {
+            Ok(()) => println!("✅ Chat {chat_id}"),
+            Err(err) => problems.push(format!("No access to chat {chat_id}: {err:#}")),
+        }
+    }
+
+    problems
+}
+
+/// `check`, which expects the configuration to be loaded already and the peers to be initialized.
+pub async fn run(client: &Client, config: &Config) -> Result<()> {
+    let mut problems = asset_problems(&config.translation);
+    problems.extend(chat_problems(client, config).await);
+
+    if problems.is_empty() {
+        println!("✅ Everything is in order");
+        Ok(())
+    } else {
+        Err(anyhow!("Check failed:\n - {}", problems.join("\n - ")))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedded_assets_are_valid() {
+        // Arrange
+        let config = TranslationConfig { glossary_dir: "res".to_string(), ..TranslationConfig::default() };
+
+        // Act
+        let problems = asset_problems(&config);
+
+        // Assert
+        assert!(problems.is_empty(), "{problems:?}");
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::{bail, Result};
+
+use crate::config::Scope;
+
+pub const USAGE: &str = "\
+Usage: mn-rs [<command>]
+
+Commands:
+  run                                             Mirror the source chats, the default
+  login                                           Sign in and save the session
+  check                                           Validate the configuration, the assets and the access to every chat
+  backfill <lang> <from>[..<to>] [<source chat>]  Translate historical posts into one language
+  translate <lang> [<source lang>]                Translate stdin and print the result
+  export <file.tmx>                               Export the translation memory
+  import <file.tmx>                               Import a translation memory";
+
+/// What the binary was asked to do.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run,
+    Login,
+    Check,
+    /// The arguments are checked by `backfill::run`.
+    Backfill(Vec<String>),
+    /// The source language is detected if missing.
+    Translate { lang: String, source_lang: Option<String> },
+    Export(String),
+    Import(String),
+}
+
+impl Command {
+    /// The settings the command needs to run.
+    pub fn scope(&self) -> Scope {
+        match self {
+            Command::Translate { .. } => Scope::Translation,
+            Command::Export(_) | Command::Import(_) => Scope::Database,
+            Command::Run | Command::Login | Command::Check | Command::Backfill(_) => Scope::Full,
+        }
+    }
+}
+
+/// Parses the arguments following the binary name.
+pub fn parse(args: &[String]) -> Result<Command> {
+    let args = args.iter().map(String::as_str).collect::<Vec<&str>>();
+
+    let command = match args.as_slice() {
+        [] | ["run"] => Command::Run,
+        ["login"] => Command::Login,
+        ["check"] => Command::Check,
+        ["backfill", rest @ ..] => Command::Backfill(rest.iter().map(ToString::to_string).collect()),
+        ["translate", lang] => Command::Translate { lang: lang.to_string(), source_lang: None },
+        ["translate", lang, source_lang] => Command::Translate { lang: lang.to_string(), source_lang: Some(source_lang.to_string()) },
+        ["export", path] => Command::Export(path.to_string()),
+        ["import", path] => Command::Import(path.to_string()),
+        _ => bail!("{USAGE}"),
+    };
+
+    Ok(command)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &str) -> Result<Command> {
+        parse(&args.split_whitespace().map(ToString::to_string).collect::<Vec<String>>())
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(parse_args("").unwrap(), Command::Run);
+        assert_eq!(parse_args("login").unwrap(), Command::Login);
+        assert_eq!(parse_args("backfill en 1200..1350").unwrap(), Command::Backfill(vec!["en".to_string(), "1200..1350".to_string()]));
+        assert_eq!(parse_args("translate en de").unwrap(), Command::Translate { lang: "en".to_string(), source_lang: Some("de".to_string()) });
+        assert_eq!(parse_args("export memory.tmx").unwrap(), Command::Export("memory.tmx".to_string()));
+    }
+
+    #[test]
+    fn test_parse_invalid_commands() {
+        assert!(parse_args("export").is_err());
+        assert!(parse_args("translate").is_err());
+        assert!(parse_args("serve").is_err());
+    }
+}
@@ -0,0 +1,500 @@
+use std::any::type_name;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::lang::{LangKey, Language};
+use crate::routing::{self, Route, RouteConfig};
+use crate::translation::Engine;
+
+/// Read if it exists, unless `CONFIG_FILE` points somewhere else.
+const DEFAULT_CONFIG_FILE: &str = "config.yaml";
+
+/// Posts older than this are not recovered unless configured otherwise.
+const DEFAULT_RECOVERY_MAX_AGE_HOURS: i64 = 24;
+
+/// Longer FLOOD_WAITs fail the send and leave the job for a later attempt.
+const DEFAULT_MAX_FLOOD_WAIT_SECS: u64 = 60;
+
+/// Leaves some of the 30 seconds container runtimes usually grant before killing the process.
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 25;
+
+#[derive(Error, Debug)]
+#[error("Invalid configuration:\n - {}", .problems.join("\n - "))]
+pub struct ConfigError {
+    pub problems: Vec<String>,
+}
+
+/// Engine the translations fall back to when no DeepL key works.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FallbackEngine {
+    #[default]
+    Google,
+    Libre,
+}
+
+impl FromStr for FallbackEngine {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "google" => Ok(Self::Google),
+            "libre" => Ok(Self::Libre),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ShadowMode {
+    #[default]
+    Off,
+    Log,
+    Chat,
+}
+
+impl FromStr for ShadowMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" | "off" => Ok(Self::Off),
+            "log" => Ok(Self::Log),
+            "chat" => Ok(Self::Chat),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A password or login code, kept out of `Debug` output and therefore out of the logs.
+#[derive(Clone, Default, PartialEq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: &str) -> Self {
+        Self(value.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(***)")
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TelegramConfig {
+    pub api_id: i32,
+    pub api_hash: String,
+    pub phone: String,
+    pub login: LoginConfig,
+}
+
+/// Where `login` takes the code and the 2FA password from, it prompts for whatever is missing.
+#[derive(Debug, Clone, Default)]
+pub struct LoginConfig {
+    /// File `login` polls for the code once it was requested, as every login requests a new code.
+    pub code_file: Option<PathBuf>,
+    pub password: Option<Secret>,
+    /// Unix socket `login` waits on for the code and password, for deployments without a terminal.
+    /// Its directory is created private if missing and has to be private otherwise.
+    pub socket: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TranslationConfig {
+    /// Tried in order, the index doubles as the key of the DeepL glossaries.
+    pub deepl_keys: Vec<String>,
+    pub fallback_engine: Engine,
+    /// Carry entities through translation directly instead of converting them to HTML and back.
+    pub entity_pipeline: bool,
+    /// Send custom emoji if the account is able to.
+    pub custom_emoji: bool,
+    /// Holds the glossaries edited from the log group, so it has to be persistent storage.
+    pub glossary_dir: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ShadowConfig {
+    pub mode: ShadowMode,
+    /// Test chat for `ShadowMode::Chat`.
+    pub chat: i64,
+}
+
+/// The settings a command depends on, only those are required.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    /// The translation settings, e.g. for `translate`.
+    Translation,
+    /// The translation settings and the database, e.g. for `export`.
+    Database,
+    /// Telegram, the database and the log group as well.
+    Full,
+}
+
+#[derive(Clone)]
+pub struct Config {
+    pub telegram: TelegramConfig,
+    pub database_url: String,
+    pub log_group: i64,
+    pub translation: TranslationConfig,
+    pub shadow: ShadowConfig,
+    pub recovery_max_age_hours: i64,
+    /// How long in-flight jobs may take to finish on shutdown, unfinished ones resume on the next start.
+    pub shutdown_timeout_secs: u64,
+    pub max_flood_wait_secs: u64,
+    /// Translations into these are posted as drafts to the log group and need an editor's approval.
+    pub review_languages: Vec<LangKey>,
+    pub sources: Vec<Route>,
+}
+
+impl Config {
+    /// Loads the optional YAML file, overridden by the environment, and reports every problem at once.
+    /// Settings outside of `scope` are read if present but not required.
+    pub fn load(scope: Scope) -> Result<Self, ConfigError> {
+        let env = |var: &str| std::env::var(var).ok();
+        let mut loader = Loader::new(&env);
+
+        let file = read_file(std::env::var("CONFIG_FILE").ok().as_deref()).unwrap_or_else(|problem| {
+            loader.problems.push(problem);
+            FileConfig::default()
+        });
+
+        Self::from_sources(file, loader, scope)
+    }
+
+    fn from_sources(file: FileConfig, mut loader: Loader, scope: Scope) -> Result<Self, ConfigError> {
+        let env = loader.env;
+        let full = scope >= Scope::Full;
+
+        let telegram = TelegramConfig {
+            api_id: loader.required_if(full, "TG_ID", "telegram.api_id", file.telegram.api_id),
+            api_hash: loader.required_if(full, "TG_HASH", "telegram.api_hash", file.telegram.api_hash),
+            phone: loader.required_if(full, "TG_MOBILE_NUMBER", "telegram.phone", file.telegram.phone),
+            login: LoginConfig {
+                code_file: loader.value("TG_LOGIN_CODE_FILE", None),
+                password: loader.secret("TG_PASSWORD", file.telegram.password_file),
+                socket: loader.value("TG_LOGIN_SOCKET", file.telegram.login_socket),
+            },
+        };
+        let database_url = loader.required_if(scope >= Scope::Database, "DATABASE_URL", "database_url", file.database_url);
+        let log_group = loader.required_if(full, "LOG_GROUP", "log_group", file.log_group);
+
+        let env_keys = (0..).map_while(|i| env(&format!("DEEPL_{i}"))).collect::<Vec<String>>();
+        let deepl_keys = if env_keys.is_empty() { file.translation.deepl_keys.unwrap_or_default() } else { env_keys };
+
+        let fallback_engine = match loader.value("FALLBACK_ENGINE", file.translation.fallback_engine).unwrap_or_default() {
+            FallbackEngine::Google => Engine::Google,
+            FallbackEngine::Libre => Engine::Libre {
+                url: loader.required("LIBRE_URL", "translation.libre_url", file.translation.libre_url),
+                api_key: loader.value("LIBRE_API_KEY", file.translation.libre_api_key).unwrap_or_default(),
+            },
+        };
+
+        let translation = TranslationConfig {
+            deepl_keys,
+            fallback_engine,
+            entity_pipeline: loader.value("ENTITY_PIPELINE", file.translation.entity_pipeline).unwrap_or(false),
+            custom_emoji: loader.value("CUSTOM_EMOJI", file.translation.custom_emoji).unwrap_or(true),
+            glossary_dir: loader.value("GLOSSARY_DIR", file.translation.glossary_dir).unwrap_or_else(|| "res".to_string()),
+        };
+
+        let mode = loader.value("SHADOW_MODE", file.shadow.mode).unwrap_or_default();
+        let shadow = ShadowConfig {
+            mode,
+            chat: match mode {
+                ShadowMode::Chat => loader.required("SHADOW_CHAT", "shadow.chat", file.shadow.chat),
+                _ => 0,
+            },
+        };
+
+        let recovery_max_age_hours = loader.value("RECOVERY_MAX_AGE_HOURS", file.recovery_max_age_hours)
+            .unwrap_or(DEFAULT_RECOVERY_MAX_AGE_HOURS);
+        let shutdown_timeout_secs = loader.value("SHUTDOWN_TIMEOUT_SECS", file.shutdown_timeout_secs)
+            .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_SECS);
+        let max_flood_wait_secs = loader.value("MAX_FLOOD_WAIT_SECS", file.max_flood_wait_secs)
+            .unwrap_or(DEFAULT_MAX_FLOOD_WAIT_SECS);
+
+        let review_languages = env("REVIEW_LANGUAGES")
+            .map(|list| list.split(',').map(|code| code.trim().to_string()).filter(|code| !code.is_empty()).collect())
+            .or(file.review_languages)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|code| LangKey::from_code(&code).or_else(|| {
+                loader.problems.push(format!("REVIEW_LANGUAGES contains the unknown language `{code}`"));
+                None
+            }))
+            .collect::<Vec<LangKey>>();
+
+        let sources = match file.sources {
+            None => vec![routing::default_route()],
+            Some(sources) => routing::build_routes(sources).unwrap_or_else(|problems| {
+                loader.problems.extend(problems);
+                Vec::new()
+            }),
+        };
+
+        if !loader.problems.is_empty() {
+            return Err(ConfigError { problems: loader.problems });
+        }
+
+        Ok(Self { telegram, database_url, log_group, translation, shadow, recovery_max_age_hours, shutdown_timeout_secs, max_flood_wait_secs, review_languages, sources })
+    }
+
+    pub fn route_for(&self, chat_id: i64) -> Option<&Route> {
+        self.sources.iter().find(|route| route.source_chat == chat_id)
+    }
+
+    pub fn needs_review(&self, lang: &Language) -> bool {
+        self.review_languages.contains(&lang.lang_key)
+    }
+}
+
+fn read_file(path: Option<&str>) -> Result<FileConfig, String> {
+    let (path, required) = match path {
+        Some(path) => (path, true),
+        None => (DEFAULT_CONFIG_FILE, false),
+    };
+
+    if !required && !Path::new(path).exists() {
+        return Ok(FileConfig::default());
+    }
+
+    let yaml = std::fs::read_to_string(path).map_err(|e| format!("Reading {path} failed: {e}"))?;
+    serde_yml::from_str(&yaml).map_err(|e| format!("Parsing {path} failed: {e}"))
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    telegram: FileTelegramConfig,
+    database_url: Option<String>,
+    log_group: Option<i64>,
+    translation: FileTranslationConfig,
+    shadow: FileShadowConfig,
+    recovery_max_age_hours: Option<i64>,
+    shutdown_timeout_secs: Option<u64>,
+    max_flood_wait_secs: Option<u64>,
+    review_languages: Option<Vec<String>>,
+    sources: Option<Vec<RouteConfig>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileTelegramConfig {
+    api_id: Option<i32>,
+    api_hash: Option<String>,
+    phone: Option<String>,
+    password_file: Option<PathBuf>,
+    login_socket: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileTranslationConfig {
+    deepl_keys: Option<Vec<String>>,
+    fallback_engine: Option<FallbackEngine>,
+    libre_url: Option<String>,
+    libre_api_key: Option<String>,
+    entity_pipeline: Option<bool>,
+    custom_emoji: Option<bool>,
+    glossary_dir: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileShadowConfig {
+    mode: Option<ShadowMode>,
+    chat: Option<i64>,
+}
+
+/// Collects the problems instead of stopping at the first one.
+struct Loader<'a> {
+    env: &'a dyn Fn(&str) -> Option<String>,
+    problems: Vec<String>,
+}
+
+impl<'a> Loader<'a> {
+    fn new(env: &'a dyn Fn(&str) -> Option<String>) -> Self {
+        Self { env, problems: Vec::new() }
+    }
+
+    /// The environment variable `var` wins over the value from the config file.
+    fn value<T: FromStr>(&mut self, var: &str, file: Option<T>) -> Option<T> {
+        let Some(raw) = (self.env)(var) else {
+            return file;
+        };
+
+        match raw.parse() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                let type_name = type_name::<T>().rsplit("::").next().unwrap_or_default();
+                self.problems.push(format!("{var} `{raw}` is not a valid {type_name}"));
+                None
+            }
+        }
+    }
+
+    /// `var` itself or the contents of the file named by `<var>_FILE`, e.g. a container secret.
+    fn secret(&mut self, var: &str, file: Option<PathBuf>) -> Option<Secret> {
+        if let Some(value) = (self.env)(var) {
+            return Some(Secret::new(&value));
+        }
+
+        let path = self.value(&format!("{var}_FILE"), file)?;
+        match std::fs::read_to_string(&path) {
+            Ok(value) => Some(Secret::new(&value)),
+            Err(e) => {
+                self.problems.push(format!("Reading {var} from {} failed: {e}", path.display()));
+                None
+            }
+        }
+    }
+
+    fn required<T: FromStr + Default>(&mut self, var: &str, key: &str, file: Option<T>) -> T {
+        let set = (self.env)(var).is_some();
+        self.value(var, file).unwrap_or_else(|| {
+            if !set {
+                self.problems.push(format!("{var} (or `{key}` in the config file) is missing"));
+            }
+            T::default()
+        })
+    }
+
+    /// `required` if `needed`, otherwise the value if there is one.
+    fn required_if<T: FromStr + Default>(&mut self, needed: bool, var: &str, key: &str, file: Option<T>) -> T {
+        if needed {
+            self.required(var, key, file)
+        } else {
+            self.value(var, file).unwrap_or_default()
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars = vars.iter()
+            .map(|(var, value)| (var.to_string(), value.to_string()))
+            .collect::<HashMap<String, String>>();
+        move |var| vars.get(var).cloned()
+    }
+
+    #[test]
+    fn test_env_overrides_file() {
+        // Arrange
+        let file: FileConfig = serde_yml::from_str("
+            telegram: { api_id: 1, api_hash: hash, phone: '+49' }
+            database_url: postgres://file
+            log_group: 42
+            translation: { deepl_keys: [file-key] }
+        ").unwrap();
+        let env = env(&[("DATABASE_URL", "postgres://env"), ("DEEPL_0", "env-key"), ("SHADOW_MODE", "log")]);
+
+        // Act
+        let config = Config::from_sources(file, Loader::new(&env), Scope::Full).unwrap();
+
+        // Assert
+        assert_eq!(config.database_url, "postgres://env");
+        assert_eq!(config.log_group, 42);
+        assert_eq!(config.translation.deepl_keys, vec!["env-key"]);
+        assert_eq!(config.shadow.mode, ShadowMode::Log);
+        assert_eq!(config.recovery_max_age_hours, DEFAULT_RECOVERY_MAX_AGE_HOURS);
+    }
+
+    #[test]
+    fn test_password_from_secret_file() {
+        // Arrange
+        let path = std::env::temp_dir().join("mn-rs-test-password");
+        std::fs::write(&path, "hunter2\n").unwrap();
+        let file: FileConfig = serde_yml::from_str("
+            telegram: { api_id: 1, api_hash: hash, phone: '+49' }
+            database_url: postgres://file
+            log_group: 42
+        ").unwrap();
+        let env = env(&[("TG_PASSWORD_FILE", path.to_str().unwrap())]);
+
+        // Act
+        let login = Config::from_sources(file, Loader::new(&env), Scope::Full).unwrap().telegram.login;
+
+        // Assert
+        assert_eq!(login.password.as_ref().map(Secret::expose), Some("hunter2"));
+        assert!(!format!("{login:?}").contains("hunter2"));
+    }
+
+    #[test]
+    fn test_login_code_file_is_not_read() {
+        // Arrange
+        let path = std::env::temp_dir().join("mn-rs-test-missing-login-code");
+        let env = env(&[("TG_LOGIN_CODE_FILE", path.to_str().unwrap())]);
+
+        // Act
+        let config = Config::from_sources(FileConfig::default(), Loader::new(&env), Scope::Translation).unwrap();
+
+        // Assert
+        assert_eq!(config.telegram.login.code_file, Some(path));
+    }
+
+    #[test]
+    fn test_reports_every_problem() {
+        // Arrange
+        let env = env(&[("TG_ID", "abc"), ("SHADOW_MODE", "chat"), ("FALLBACK_ENGINE", "libre")]);
+
+        // Act
+        let problems = Config::from_sources(FileConfig::default(), Loader::new(&env), Scope::Full).err().unwrap().problems;
+
+        // Assert
+        assert_eq!(problems, vec![
+            "TG_ID `abc` is not a valid i32",
+            "TG_HASH (or `telegram.api_hash` in the config file) is missing",
+            "TG_MOBILE_NUMBER (or `telegram.phone` in the config file) is missing",
+            "DATABASE_URL (or `database_url` in the config file) is missing",
+            "LOG_GROUP (or `log_group` in the config file) is missing",
+            "LIBRE_URL (or `translation.libre_url` in the config file) is missing",
+            "SHADOW_CHAT (or `shadow.chat` in the config file) is missing",
+        ]);
+    }
+
+    #[test]
+    fn test_scope_limits_required_settings() {
+        // Arrange
+        let env = env(&[("DEEPL_0", "env-key")]);
+
+        // Act
+        let translation = Config::from_sources(FileConfig::default(), Loader::new(&env), Scope::Translation);
+        let database = Config::from_sources(FileConfig::default(), Loader::new(&env), Scope::Database);
+
+        // Assert
+        assert_eq!(translation.unwrap().translation.deepl_keys, vec!["env-key"]);
+        assert_eq!(database.err().unwrap().problems, vec!["DATABASE_URL (or `database_url` in the config file) is missing"]);
+    }
+
+    #[test]
+    fn test_review_languages() {
+        // Arrange
+        let file: FileConfig = serde_yml::from_str("review_languages: [en]").unwrap();
+        let (no_env, invalid_env) = (env(&[]), env(&[("REVIEW_LANGUAGES", "en, xx")]));
+
+        // Act
+        let from_file = Config::from_sources(file, Loader::new(&no_env), Scope::Translation).unwrap();
+        let problems = Config::from_sources(FileConfig::default(), Loader::new(&invalid_env), Scope::Translation).err().unwrap().problems;
+
+        // Assert
+        assert_eq!(from_file.review_languages, vec![LangKey::EN]);
+        assert_eq!(problems, vec!["REVIEW_LANGUAGES contains the unknown language `xx`"]);
+    }
+}
@@ -1,9 +1,22 @@
 
-use sqlx::{query, PgPool};
+use grammers_session::PackedChat;
+use sqlx::{PgExecutor, PgPool};
 use anyhow::Result;
-use sqlx::postgres::PgQueryResult;
 use thiserror::Error;
 
+/// Minimum trigram similarity for a translation memory entry to count as a fuzzy match.
+const FUZZY_MATCH_THRESHOLD: f32 = 0.85;
+
+/// Jobs are moved back to `pending` on failure until they ran out of attempts.
+const MAX_JOB_ATTEMPTS: i32 = 3;
+
+/// Delay before retrying a failed job, multiplied by four with every further attempt,
+/// so a short DeepL or Telegram outage doesn't use up all attempts at once.
+const JOB_RETRY_DELAY_SECS: f64 = 60.0;
+
+/// A job claimed longer ago than this was abandoned by a crashed process and may be claimed again.
+const JOB_LEASE_SECS: f64 = 15.0 * 60.0;
+
 
 #[derive(Error, Debug)]
  enum DatabaseError {
@@ -14,6 +27,12 @@ use thiserror::Error;
         e: sqlx::Error,
     },
 
+    #[error("Updating job {job_id} failed: {e:?}")]
+    UpdateJob {
+        job_id: i32,
+        e: sqlx::Error,
+    },
+
 }
 
 
@@ -30,22 +49,350 @@ pub struct Post {
 }
 
 impl Post {
-    pub async fn insert(lang: String, msg_id: i32, db_pool: &PgPool) -> Result<i32>{
+    /// Returns the id of the message a source post was already mirrored as, if any.
+    pub async fn find_mirrored(source_chat: i64, source_msg_id: i32, lang: &str, db_pool: &PgPool) -> Result<Option<i32>> {
+        Ok(sqlx::query_scalar(
+            "SELECT msg_id FROM posts WHERE source_chat = $1 AND source_msg_id = $2 AND lang = $3;",
+        )
+            .bind(source_chat)
+            .bind(source_msg_id)
+            .bind(lang)
+            .fetch_optional(db_pool)
+            .await?)
+    }
 
+    /// Whether any post was mirrored with this database yet.
+    pub async fn any(db_pool: &PgPool) -> Result<bool> {
+        Ok(sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM posts);")
+            .fetch_one(db_pool)
+            .await?)
+    }
 
-        let result= query!(
-            "INSERT INTO posts (	lang,	msg_id) VALUES ($1, $2) returning post_id ;",
-            lang,
-            msg_id,
+    pub async fn insert(lang: String, msg_id: i32, source_chat: i64, source_msg_id: i32, executor: impl PgExecutor<'_>) -> Result<i32> {
+        let post_id: Option<i32> = sqlx::query_scalar(
+            "INSERT INTO posts (lang, msg_id, source_chat, source_msg_id) VALUES ($1, $2, $3, $4) returning post_id;",
         )
-        .fetch_one(db_pool)
-        .await
+            .bind(lang)
+            .bind(msg_id)
+            .bind(source_chat)
+            .bind(source_msg_id)
+            .fetch_one(executor)
+            .await
             .map_err(|e| DatabaseError::InsertPost { msg_id, e })?;
 
 //todo: post_id sollte not null sein?????
 
-        Ok(result.post_id.unwrap_or(0) )
+        Ok(post_id.unwrap_or(0))
     }
 }
 
 
+#[derive(sqlx::Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum JobState {
+    Pending,
+    Translating,
+    /// Waiting for an editor to approve the draft in the log group.
+    Review,
+    Sent,
+    Rejected,
+    Failed,
+}
+
+/// One language of one source post, waiting to be published.
+#[derive(sqlx::FromRow, Debug)]
+pub struct Job {
+    pub job_id: i32,
+    pub source_chat: Vec<u8>,
+    pub source_msg_id: i32,
+    pub lang: String,
+    pub state: JobState,
+    pub attempts: i32,
+    pub draft_text: Option<String>,
+}
+
+/// A job joined with the source chat id of the post it was mirrored as.
+#[derive(sqlx::FromRow)]
+struct MirroredJob {
+    post_source_chat: i64,
+    #[sqlx(flatten)]
+    job: Job,
+}
+
+impl Job {
+    /// Redelivered updates hit the unique source index and don't create duplicate jobs.
+    /// Returns the ids of the jobs actually created.
+    pub async fn enqueue(source_chat: &[u8], source_msg_id: i32, langs: &[String], db_pool: &PgPool) -> Result<Vec<i32>> {
+        Ok(sqlx::query_scalar(
+            "INSERT INTO jobs (source_chat, source_msg_id, lang) SELECT $1, $2, unnest($3::varchar[])
+             ON CONFLICT (source_chat, source_msg_id, lang) DO NOTHING
+             RETURNING job_id;",
+        )
+            .bind(source_chat)
+            .bind(source_msg_id)
+            .bind(langs)
+            .fetch_all(db_pool)
+            .await?)
+    }
+
+    /// Claims the oldest job that is due, or whose claim expired, for `worker` and marks it as `translating`.
+    /// `only` restricts the claim to the given jobs.
+    pub async fn claim_next(worker: &str, only: Option<&[i32]>, db_pool: &PgPool) -> Result<Option<Job>> {
+        Ok(sqlx::query_as::<_, Job>(
+            "UPDATE jobs SET state = 'translating', claimed_by = $1, attempts = attempts + 1, updated_at = now()
+             WHERE job_id = (
+                 SELECT job_id FROM jobs
+                 WHERE ((state = 'pending' AND not_before <= now())
+                    OR (state = 'translating' AND updated_at < now() - make_interval(secs => $2)))
+                   AND ($3::int[] IS NULL OR job_id = ANY($3))
+                 ORDER BY job_id FOR UPDATE SKIP LOCKED LIMIT 1
+             )
+             RETURNING job_id, source_chat, source_msg_id, lang, state, attempts, draft_text;",
+        )
+            .bind(worker)
+            .bind(JOB_LEASE_SECS)
+            .bind(only)
+            .fetch_optional(db_pool)
+            .await?)
+    }
+
+    /// Returns every packed source chat with the newest source message id that was ever queued from it.
+    pub async fn last_sources(db_pool: &PgPool) -> Result<Vec<(Vec<u8>, i32)>> {
+        Ok(sqlx::query_as(
+            "SELECT source_chat, max(source_msg_id) FROM jobs GROUP BY source_chat;",
+        )
+            .fetch_all(db_pool)
+            .await?)
+    }
+
+    /// Puts the jobs `worker` left in `translating` back into the queue.
+    /// Jobs of other processes are left alone, they are only taken over once their claim expired.
+    pub async fn reset_interrupted(worker: &str, db_pool: &PgPool) -> Result<u64> {
+        Ok(sqlx::query(
+            "UPDATE jobs SET state = 'pending', claimed_by = NULL, updated_at = now()
+             WHERE state = 'translating' AND claimed_by = $1;",
+        )
+            .bind(worker)
+            .execute(db_pool)
+            .await?
+            .rows_affected())
+    }
+
+    pub async fn mark_sent(&self, executor: impl PgExecutor<'_>) -> Result<()> {
+        let job_id = self.job_id;
+        sqlx::query("UPDATE jobs SET state = 'sent', last_error = NULL, updated_at = now() WHERE job_id = $1;")
+            .bind(job_id)
+            .execute(executor)
+            .await
+            .map_err(|e| DatabaseError::UpdateJob { job_id, e })?;
+
+        Ok(())
+    }
+
+    /// Parks the job until an editor acts on the draft posted as `draft_msg_id`.
+    pub async fn mark_review(&self, draft_msg_id: i32, draft_text: &str, db_pool: &PgPool) -> Result<()> {
+        let job_id = self.job_id;
+        sqlx::query("UPDATE jobs SET state = 'review', draft_msg_id = $2, draft_text = $3, updated_at = now() WHERE job_id = $1;")
+            .bind(job_id)
+            .bind(draft_msg_id)
+            .bind(draft_text)
+            .execute(db_pool)
+            .await
+            .map_err(|e| DatabaseError::UpdateJob { job_id, e })?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_draft(draft_msg_id: i32, db_pool: &PgPool) -> Result<Option<Job>> {
+        Ok(sqlx::query_as::<_, Job>(
+            "SELECT job_id, source_chat, source_msg_id, lang, state, attempts, draft_text FROM jobs
+             WHERE draft_msg_id = $1 AND state = 'review';",
+        )
+            .bind(draft_msg_id)
+            .fetch_optional(db_pool)
+            .await?)
+    }
+
+    /// Replaces the machine translation of a draft with the editor's text.
+    pub async fn update_draft(&self, draft_text: &str, db_pool: &PgPool) -> Result<()> {
+        let job_id = self.job_id;
+        sqlx::query("UPDATE jobs SET draft_text = $2, updated_at = now() WHERE job_id = $1;")
+            .bind(job_id)
+            .bind(draft_text)
+            .execute(db_pool)
+            .await
+            .map_err(|e| DatabaseError::UpdateJob { job_id, e })?;
+
+        Ok(())
+    }
+
+    pub async fn mark_rejected(&self, db_pool: &PgPool) -> Result<()> {
+        let job_id = self.job_id;
+        sqlx::query("UPDATE jobs SET state = 'rejected', updated_at = now() WHERE job_id = $1;")
+            .bind(job_id)
+            .execute(db_pool)
+            .await
+            .map_err(|e| DatabaseError::UpdateJob { job_id, e })?;
+
+        Ok(())
+    }
+
+    /// Finds the job a mirrored post in a language channel was published by.
+    pub async fn find_by_post(lang: &str, msg_id: i32, db_pool: &PgPool) -> Result<Option<Job>> {
+        let candidates = sqlx::query_as::<_, MirroredJob>(
+            "SELECT p.source_chat AS post_source_chat, j.job_id, j.source_chat, j.source_msg_id, j.lang, j.state, j.attempts, j.draft_text
+             FROM jobs j
+             JOIN posts p ON p.source_msg_id = j.source_msg_id AND p.lang = j.lang
+             WHERE p.lang = $1 AND p.msg_id = $2;",
+        )
+            .bind(lang)
+            .bind(msg_id)
+            .fetch_all(db_pool)
+            .await?;
+
+        // Jobs store the packed source chat, so the chat ids are compared after unpacking it.
+        Ok(candidates.into_iter()
+            .find(|candidate| PackedChat::from_bytes(&candidate.job.source_chat)
+                .is_ok_and(|chat| chat.id == candidate.post_source_chat))
+            .map(|candidate| candidate.job))
+    }
+
+    /// Records the mirrored message and marks the job as sent in one transaction.
+    pub async fn complete(&self, source_chat: i64, msg_id: i32, db_pool: &PgPool) -> Result<()> {
+        let mut tx = db_pool.begin().await?;
+        Post::insert(self.lang.clone(), msg_id, source_chat, self.source_msg_id, &mut *tx).await?;
+        self.mark_sent(&mut *tx).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Puts the job back into the queue after a delay, or into the dead-letter state once it ran out of attempts.
+    pub async fn mark_failed(&self, error: &str, db_pool: &PgPool) -> Result<JobState> {
+        let job_id = self.job_id;
+        let state = if self.attempts >= MAX_JOB_ATTEMPTS { JobState::Failed } else { JobState::Pending };
+        let retry_delay = JOB_RETRY_DELAY_SECS * 4f64.powi(self.attempts.max(1) - 1);
+
+        sqlx::query(
+            "UPDATE jobs SET state = $2, last_error = $3, not_before = now() + make_interval(secs => $4), updated_at = now()
+             WHERE job_id = $1;",
+        )
+            .bind(job_id)
+            .bind(state)
+            .bind(error)
+            .bind(retry_delay)
+            .execute(db_pool)
+            .await
+            .map_err(|e| DatabaseError::UpdateJob { job_id, e })?;
+
+        Ok(state)
+    }
+
+    /// Moves every dead-lettered job back into the queue with a fresh attempt budget.
+    pub async fn retry_failed(db_pool: &PgPool) -> Result<u64> {
+        Ok(sqlx::query("UPDATE jobs SET state = 'pending', attempts = 0, not_before = now(), updated_at = now() WHERE state = 'failed';")
+            .execute(db_pool)
+            .await?
+            .rows_affected())
+    }
+}
+
+
+/// A human-corrected pair of source and target segments.
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq)]
+pub struct MemoryEntry {
+    pub lang: String,
+    pub source: String,
+    pub target: String,
+}
+
+impl MemoryEntry {
+    /// Returns the exact match, or the most similar fuzzy match.
+    pub async fn lookup(lang: &str, source: &str, db_pool: &PgPool) -> Result<Option<MemoryEntry>> {
+        Ok(sqlx::query_as::<_, MemoryEntry>(
+            "SELECT lang, source, target FROM translation_memory
+             WHERE lang = $1 AND (source = $2 OR (source % $2 AND similarity(source, $2) >= $3))
+             ORDER BY source = $2 DESC, similarity(source, $2) DESC
+             LIMIT 1;",
+        )
+            .bind(lang)
+            .bind(source)
+            .bind(FUZZY_MATCH_THRESHOLD)
+            .fetch_optional(db_pool)
+            .await?)
+    }
+
+    /// Stores the entry, a newer correction of the same source segment wins.
+    pub async fn upsert(&self, db_pool: &PgPool) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO translation_memory (lang, source, target) VALUES ($1, $2, $3)
+             ON CONFLICT (lang, source) DO UPDATE SET target = excluded.target, updated_at = now();",
+        )
+            .bind(&self.lang)
+            .bind(&self.source)
+            .bind(&self.target)
+            .execute(db_pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn all(db_pool: &PgPool) -> Result<Vec<MemoryEntry>> {
+        Ok(sqlx::query_as::<_, MemoryEntry>("SELECT lang, source, target FROM translation_memory ORDER BY tm_id;")
+            .fetch_all(db_pool)
+            .await?)
+    }
+}
+
+
+/// A resolved chat, `packed` is a `PackedChat` carrying the access hash.
+#[derive(sqlx::FromRow, Debug)]
+pub struct Peer {
+    pub chat_id: i64,
+    pub packed: Vec<u8>,
+}
+
+impl Peer {
+    pub async fn all(db_pool: &PgPool) -> Result<Vec<Peer>> {
+        Ok(sqlx::query_as::<_, Peer>("SELECT chat_id, packed FROM peers;")
+            .fetch_all(db_pool)
+            .await?)
+    }
+
+    pub async fn upsert(&self, db_pool: &PgPool) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO peers (chat_id, packed) VALUES ($1, $2)
+             ON CONFLICT (chat_id) DO UPDATE SET packed = excluded.packed, updated_at = now();",
+        )
+            .bind(self.chat_id)
+            .bind(&self.packed)
+            .execute(db_pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+
+/// The serialized Telegram session, a single row.
+pub struct StoredSession;
+
+impl StoredSession {
+    pub async fn load(db_pool: &PgPool) -> Result<Option<Vec<u8>>> {
+        Ok(sqlx::query_scalar::<_, Vec<u8>>("SELECT data FROM telegram_session WHERE id = 1;")
+            .fetch_optional(db_pool)
+            .await?)
+    }
+
+    pub async fn save(data: &[u8], db_pool: &PgPool) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO telegram_session (id, data) VALUES (1, $1)
+             ON CONFLICT (id) DO UPDATE SET data = excluded.data, updated_at = now();",
+        )
+            .bind(data)
+            .execute(db_pool)
+            .await?;
+
+        Ok(())
+    }
+}
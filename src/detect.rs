@@ -0,0 +1,59 @@
+use tracing::{info, warn};
+
+use crate::lang::LangKey;
+
+/// Used when a post is too short or too mixed to be detected reliably.
+const FALLBACK_LANG: LangKey = LangKey::DE;
+
+fn lang_key_of(lang: whatlang::Lang) -> Option<LangKey> {
+    match lang {
+        whatlang::Lang::Deu => Some(LangKey::DE),
+        whatlang::Lang::Eng => Some(LangKey::EN),
+        whatlang::Lang::Ara => Some(LangKey::AR),
+        _ => None,
+    }
+}
+
+/// Detects the language of `text`, `None` if it isn't one we translate from or the detection isn't reliable.
+fn detect(text: &str) -> Option<LangKey> {
+    let info = whatlang::detect(text)?;
+    let lang = lang_key_of(info.lang());
+
+    info!(
+        target: "detect",
+        detected = info.lang().code(),
+        confidence = info.confidence(),
+        reliable = info.is_reliable(),
+        "Detected source language",
+    );
+
+    lang.filter(|_| info.is_reliable())
+}
+
+/// The language a post is written in, `configured` by its route or detected from its text.
+pub fn source_language(configured: Option<LangKey>, text: &str) -> LangKey {
+    configured
+        .or_else(|| detect(text))
+        .unwrap_or_else(|| {
+            warn!("Could not detect the source language, assuming {FALLBACK_LANG}");
+            FALLBACK_LANG
+        })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_supported_languages() {
+        assert_eq!(detect("Die ukrainischen Streitkräfte haben in der Nacht mehrere Drohnen über der Region abgeschossen."), Some(LangKey::DE));
+        assert_eq!(detect("The Ukrainian armed forces shot down several drones over the region during the night."), Some(LangKey::EN));
+        assert_eq!(detect("أسقطت القوات المسلحة الأوكرانية عدة طائرات مسيرة فوق المنطقة خلال الليل."), Some(LangKey::AR));
+    }
+
+    #[test]
+    fn test_detect_unsupported_language() {
+        assert_eq!(detect("Украинские вооружённые силы ночью сбили несколько беспилотников над регионом."), None);
+    }
+}
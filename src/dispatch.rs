@@ -0,0 +1,86 @@
+use grammers_client::{Client, Update};
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tokio::task::{AbortHandle, JoinHandle};
+use tracing::error;
+
+use crate::config::Config;
+use crate::{handle_error, process_update};
+
+/// Lanes process updates concurrently, while the updates of one chat always take the same lane and stay in order.
+const LANES: usize = 8;
+/// Updates waiting in a lane before receiving further ones blocks.
+const LANE_CAPACITY: usize = 64;
+
+/// Updates without a chat, like deleted messages, share the first lane.
+fn chat_of(update: &Update) -> Option<i64> {
+    match update {
+        Update::NewMessage(message) | Update::MessageEdited(message) => Some(message.chat().id()),
+        _ => None,
+    }
+}
+
+fn lane_of(chat_id: i64, lanes: usize) -> usize {
+    chat_id.rem_euclid(lanes as i64) as usize
+}
+
+pub struct Dispatcher {
+    lanes: Vec<mpsc::Sender<Update>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Dispatcher {
+    pub fn start(client: &Client, db_pool: &PgPool, config: &Config) -> Self {
+        let (lanes, workers) = (0..LANES)
+            .map(|_| {
+                let (sender, receiver) = mpsc::channel(LANE_CAPACITY);
+                let worker = tokio::spawn(run_lane(receiver, client.clone(), db_pool.clone(), config.clone()));
+                (sender, worker)
+            })
+            .unzip();
+
+        Self { lanes, workers }
+    }
+
+    /// Queues `update` in the lane of its chat, waiting while that lane is full.
+    pub async fn dispatch(&self, update: Update) {
+        let lane = chat_of(&update).map_or(0, |chat_id| lane_of(chat_id, self.lanes.len()));
+        if self.lanes[lane].send(update).await.is_err() {
+            error!("Update lane {lane} stopped, dropping the update");
+        }
+    }
+
+    /// Handles to cancel the lanes if `finish` takes too long, taken before it consumes the dispatcher.
+    pub fn abort_handles(&self) -> Vec<AbortHandle> {
+        self.workers.iter().map(JoinHandle::abort_handle).collect()
+    }
+
+    /// Stops taking updates and waits for the queued ones to be processed.
+    pub async fn finish(self) {
+        drop(self.lanes);
+        for worker in self.workers {
+            let _ = worker.await;
+        }
+    }
+}
+
+async fn run_lane(mut receiver: mpsc::Receiver<Update>, client: Client, db_pool: PgPool, config: Config) {
+    while let Some(update) = receiver.recv().await {
+        if let Err(err) = process_update(update, &client, &db_pool, &config).await {
+            let _ = handle_error(&client, &config, err).await.map_err(|e| error!("⚠️ Failed to handle error: {e:?}"));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lane_of_is_stable_and_in_range() {
+        assert_eq!(lane_of(1391125365, LANES), lane_of(1391125365, LANES));
+        assert!((0..1000).all(|chat_id| lane_of(chat_id, LANES) < LANES));
+        assert!(lane_of(-42, LANES) < LANES);
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Result;
+use grammers_client::{Client, InputMessage};
+use grammers_client::parsers::{generate_html_message, parse_html_message};
+use grammers_tl_types::enums::MessageEntity;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::formatting::{split_html, unescape_html, HtmlSegment};
+
+const MARKER_PATTERN: &str = r"^<(/?)m(\d+)>$";
+lazy_static! {
+    static ref MARKER_REGEX: Regex = Regex::new(MARKER_PATTERN).expect("Invalid regex pattern");
+}
+
+static CUSTOM_EMOJI_ALLOWED: AtomicBool = AtomicBool::new(true);
+
+/// Custom emoji need a premium account, without one they are sent as their plain fallback emoji.
+/// `enabled` turns them off regardless.
+pub async fn detect_custom_emoji_support(client: &Client, enabled: bool) -> Result<()> {
+    let allowed = enabled && client.get_me().await?.raw.premium;
+
+    CUSTOM_EMOJI_ALLOWED.store(allowed, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Entities the HTML round-trip loses, posts containing them always take the entity pipeline.
+pub fn has_premium_entities(entities: &[MessageEntity]) -> bool {
+    entities.iter().any(|entity| matches!(
+        entity,
+        MessageEntity::CustomEmoji(_) | MessageEntity::Spoiler(_) | MessageEntity::Blockquote(_) | MessageEntity::Underline(_)
+    ))
+}
+
+/// Matches every `MessageEntity` variant, binding the inner struct which carries `offset` and `length`.
+macro_rules! with_entity {
+    ($entity:expr, |$inner:ident| $body:expr) => {
+        match $entity {
+            MessageEntity::Unknown($inner) => $body,
+            MessageEntity::Mention($inner) => $body,
+            MessageEntity::Hashtag($inner) => $body,
+            MessageEntity::BotCommand($inner) => $body,
+            MessageEntity::Url($inner) => $body,
+            MessageEntity::Email($inner) => $body,
+            MessageEntity::Bold($inner) => $body,
+            MessageEntity::Italic($inner) => $body,
+            MessageEntity::Code($inner) => $body,
+            MessageEntity::Pre($inner) => $body,
+            MessageEntity::TextUrl($inner) => $body,
+            MessageEntity::MentionName($inner) => $body,
+            MessageEntity::InputMessageEntityMentionName($inner) => $body,
+            MessageEntity::Phone($inner) => $body,
+            MessageEntity::Cashtag($inner) => $body,
+            MessageEntity::Underline($inner) => $body,
+            MessageEntity::Strike($inner) => $body,
+            MessageEntity::BankCard($inner) => $body,
+            MessageEntity::Spoiler($inner) => $body,
+            MessageEntity::CustomEmoji($inner) => $body,
+            MessageEntity::Blockquote($inner) => $body,
+        }
+    };
+}
+
+/// Offset and length in UTF-16 code units, as Telegram counts them.
+pub fn range(entity: &MessageEntity) -> (i32, i32) {
+    with_entity!(entity, |inner| (inner.offset, inner.length))
+}
+
+pub fn with_range(entity: &MessageEntity, offset: i32, length: i32) -> MessageEntity {
+    let mut entity = entity.clone();
+    with_entity!(&mut entity, |inner| {
+        inner.offset = offset;
+        inner.length = length;
+    });
+    entity
+}
+
+fn utf16_len(text: &str) -> i32 {
+    text.encode_utf16().count() as i32
+}
+
+/// A translated post ready to be sent: plain text plus the entities formatting it.
+#[derive(Debug, Clone, Default)]
+pub struct Rendered {
+    pub text: String,
+    pub entities: Vec<MessageEntity>,
+}
+
+impl Rendered {
+    pub fn from_html(html: &str) -> Self {
+        let (text, entities) = parse_html_message(html);
+        Self { text, entities }
+    }
+
+    pub fn to_html(&self) -> String {
+        generate_html_message(&self.text, &self.entities)
+    }
+
+    /// Puts `prefix` in front of the text, moving the entities along.
+    pub fn prefixed(&self, prefix: &str) -> Self {
+        let shift = utf16_len(prefix);
+        Self {
+            text: format!("{prefix}{}", self.text),
+            entities: self.entities.iter()
+                .map(|entity| {
+                    let (offset, length) = range(entity);
+                    with_range(entity, offset + shift, length)
+                })
+                .collect(),
+        }
+    }
+
+    /// Cuts the sorted, non-overlapping byte `ranges` out of the text,
+    /// shrinking the entities they overlap and dropping those left empty.
+    pub fn without(&self, ranges: &[Range<usize>]) -> Self {
+        let removed = ranges.iter()
+            .map(|cut| (utf16_len(&self.text[..cut.start]), utf16_len(&self.text[..cut.end])))
+            .collect::<Vec<(i32, i32)>>();
+        let shift = |position: i32| position - removed.iter()
+            .map(|&(start, end)| (position.min(end) - start).max(0))
+            .sum::<i32>();
+
+        let mut text = String::with_capacity(self.text.len());
+        let mut last = 0;
+        for cut in ranges {
+            text.push_str(&self.text[last..cut.start]);
+            last = cut.end;
+        }
+        text.push_str(&self.text[last..]);
+
+        let entities = self.entities.iter()
+            .filter_map(|entity| {
+                let (offset, length) = range(entity);
+                let (start, end) = (shift(offset), shift(offset + length));
+                (end > start).then(|| with_range(entity, start, end - start))
+            })
+            .collect();
+
+        Self { text, entities }
+    }
+
+    /// Drops trailing whitespace, clipping the entities reaching into it.
+    pub fn trim_end(&self) -> Self {
+        let text = self.text.trim_end().to_string();
+        let end = utf16_len(&text);
+        let entities = self.entities.iter()
+            .filter_map(|entity| {
+                let (offset, length) = range(entity);
+                let length = length.min(end - offset);
+                (length > 0).then(|| with_range(entity, offset, length))
+            })
+            .collect();
+
+        Self { text, entities }
+    }
+
+    /// The entities the sending account is able to use.
+    pub fn sendable_entities(&self) -> Vec<MessageEntity> {
+        let custom_emoji_allowed = CUSTOM_EMOJI_ALLOWED.load(Ordering::Relaxed);
+        self.entities.iter()
+            .filter(|entity| custom_emoji_allowed || !matches!(entity, MessageEntity::CustomEmoji(_)))
+            .cloned()
+            .collect()
+    }
+
+    pub fn to_input_message(&self) -> InputMessage {
+        InputMessage::text(&self.text).fmt_entities(self.sendable_entities())
+    }
+}
+
+fn escape_char(out: &mut String, ch: char) {
+    match ch {
+        '&' => out.push_str("&amp;"),
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        '"' => out.push_str("&quot;"),
+        ch => out.push(ch),
+    }
+}
+
+/// Wraps every entity of `text` in a numbered `<mN>` marker which the engines carry through translation
+/// like any other tag, so the entities can be put back at their new position afterwards.
+pub fn anchor(text: &str, entities: &[MessageEntity]) -> String {
+    // (position, closing before opening, outer before inner, index)
+    let mut marks = Vec::with_capacity(entities.len() * 2);
+    for (i, entity) in entities.iter().enumerate() {
+        let (offset, length) = range(entity);
+        marks.push((offset + length, 0, -i64::from(offset), -(i as i64), i, true));
+        marks.push((offset, 1, -i64::from(length), i as i64, i, false));
+    }
+    marks.sort();
+
+    let mut anchored = String::with_capacity(text.len() + marks.len() * 5);
+    let mut marks = marks.into_iter().peekable();
+    let mut position = 0;
+
+    let mut push_marks = |anchored: &mut String, position: i32| {
+        while let Some(&(at, _, _, _, i, closing)) = marks.peek() {
+            if at > position {
+                break;
+            }
+            anchored.push_str(&if closing { format!("</m{i}>") } else { format!("<m{i}>") });
+            marks.next();
+        }
+    };
+
+    for ch in text.chars() {
+        push_marks(&mut anchored, position);
+        escape_char(&mut anchored, ch);
+        position += ch.len_utf16() as i32;
+    }
+    push_marks(&mut anchored, i32::MAX);
+
+    anchored
+}
+
+/// Turns an anchored translation back into plain text, giving each original entity the range
+/// between its markers. Entities whose markers got lost in translation are dropped.
+pub fn restore(translated: &str, entities: &[MessageEntity]) -> Rendered {
+    let mut text = String::with_capacity(translated.len());
+    let mut starts = HashMap::new();
+    let mut restored = Vec::with_capacity(entities.len());
+    let mut position = 0;
+
+    for segment in split_html(translated) {
+        match segment {
+            HtmlSegment::Text(run) => {
+                let run = unescape_html(run);
+                position += utf16_len(&run);
+                text.push_str(&run);
+            }
+            HtmlSegment::Tag(tag) => {
+                let Some(marker) = MARKER_REGEX.captures(tag) else {
+                    continue;
+                };
+                let Ok(i) = marker[2].parse::<usize>() else {
+                    continue;
+                };
+
+                if marker[1].is_empty() {
+                    starts.insert(i, position);
+                } else if let (Some(start), Some(entity)) = (starts.remove(&i), entities.get(i)) {
+                    if position > start {
+                        restored.push(with_range(entity, start, position - start));
+                    }
+                }
+            }
+        }
+    }
+
+    restored.sort_by_key(range);
+    Rendered { text, entities: restored }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use grammers_tl_types::types;
+
+    use super::*;
+
+    fn bold(offset: i32, length: i32) -> MessageEntity {
+        types::MessageEntityBold { offset, length }.into()
+    }
+
+    fn custom_emoji(offset: i32, length: i32) -> MessageEntity {
+        types::MessageEntityCustomEmoji { offset, length, document_id: 42 }.into()
+    }
+
+    #[test]
+    fn test_anchor_nested_entities() {
+        // Arrange
+        let text = "🇺🇦 Angriff auf Kiew";
+        let entities = vec![bold(5, 16), custom_emoji(0, 4), bold(17, 4)];
+
+        // Act
+        let anchored = anchor(text, &entities);
+
+        // Assert
+        assert_eq!(anchored, "<m1>🇺🇦</m1> <m0>Angriff auf <m2>Kiew</m2></m0>");
+    }
+
+    #[test]
+    fn test_restore_moves_entities() {
+        // Arrange
+        let entities = vec![bold(5, 16), custom_emoji(0, 4), bold(17, 4)];
+        let translated = "<m1>🇺🇦</m1> <m0>Attack on <m2>Kyiv</m2></m0> &amp; more";
+
+        // Act
+        let rendered = restore(translated, &entities);
+
+        // Assert
+        assert_eq!(rendered.text, "🇺🇦 Attack on Kyiv & more");
+        assert_eq!(rendered.entities, vec![custom_emoji(0, 4), bold(5, 14), bold(15, 4)]);
+    }
+
+    #[test]
+    fn test_restore_drops_lost_markers() {
+        let rendered = restore("<m0>Attack on Kyiv", &[bold(0, 14)]);
+
+        assert_eq!(rendered.text, "Attack on Kyiv");
+        assert!(rendered.entities.is_empty());
+    }
+
+    #[test]
+    fn test_has_premium_entities() {
+        assert!(has_premium_entities(&[bold(0, 4), custom_emoji(5, 2)]));
+        assert!(!has_premium_entities(&[bold(0, 4)]));
+    }
+
+    #[test]
+    fn test_without_shifts_and_drops_entities() {
+        // Arrange
+        let rendered = Rendered { text: "🇺🇦 #only:en Kyiv #skip:ru".to_string(), entities: vec![bold(5, 5), bold(14, 4)] };
+
+        // Act
+        let rendered = rendered.without(&[9..18, 22..31]);
+
+        // Assert
+        assert_eq!(rendered.text, "🇺🇦 Kyiv");
+        assert_eq!(rendered.entities, vec![bold(5, 4)]);
+    }
+
+    #[test]
+    fn test_prefixed_shifts_entities() {
+        let rendered = Rendered { text: "Kyiv".to_string(), entities: vec![bold(0, 4)] }.prefixed("📝 ");
+
+        assert_eq!(rendered.text, "📝 Kyiv");
+        assert_eq!(rendered.entities, vec![bold(3, 4)]);
+    }
+}
@@ -1,22 +1,50 @@
 use std::collections::BTreeMap;
 use std::fmt::Debug;
+use std::future::Future;
 
 use anyhow::Result;
 use include_dir::{Dir, include_dir};
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde_yml;
+use thiserror::Error;
 
 use crate::lang::Language;
 
-static ASSETS: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/res");
+pub static ASSETS: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/res");
 const FLAG_PATTERN: &str = r"\p{Regional_Indicator}{2}";
+const TAG_PATTERN: &str = r"<[^>]*>";
+const TAG_PARTS_PATTERN: &str = r#"^<(/?)([a-zA-Z][\w-]*)((?:\s+[\w-]+(?:="[^"]*")?)*)\s*/?>$"#;
+const ATTRIBUTE_PATTERN: &str = r#"([\w-]+)(?:="([^"]*)")?"#;
+
+/// Tags Telegram's HTML parser accepts, with the attributes kept on each.
+const SUPPORTED_TAGS: [(&str, &[&str]); 15] = [
+    ("b", &[]),
+    ("strong", &[]),
+    ("i", &[]),
+    ("em", &[]),
+    ("u", &[]),
+    ("ins", &[]),
+    ("s", &[]),
+    ("strike", &[]),
+    ("del", &[]),
+    ("a", &["href"]),
+    ("code", &["class"]),
+    ("pre", &[]),
+    ("tg-spoiler", &[]),
+    ("tg-emoji", &["emoji-id"]),
+    ("blockquote", &["expandable"]),
+];
+
 lazy_static! {
+    static ref TAG_PARTS_REGEX: Regex = Regex::new(TAG_PARTS_PATTERN).expect("Invalid regex pattern");
+    static ref ATTRIBUTE_REGEX: Regex = Regex::new(ATTRIBUTE_PATTERN).expect("Invalid regex pattern");
     static ref FLAG_REGEX: Regex = Regex::new(FLAG_PATTERN).expect("Invalid regex pattern");
+    static ref TAG_REGEX: Regex = Regex::new(TAG_PATTERN).expect("Invalid regex pattern");
 }
 
 pub fn add_footer(text: String, lang: &Language) -> Result<String> {
-    let f = ASSETS.get_file(format!("{}/flags.json", lang.lang_key.to_string().to_lowercase())).expect("No flags available for this lang!");
+    let f = ASSETS.get_file(format!("{}/flags.json", lang.lang_key.code())).expect("No flags available for this lang!");
 
     let flags: BTreeMap<String, String> = serde_json::from_slice(f.contents())?;
 
@@ -33,4 +61,264 @@ pub fn add_footer(text: String, lang: &Language) -> Result<String> {
     } else {
         Ok(format!("{}\n\n#{}\n{}", text, hashtags.join(" #"), lang.footer))
     }
-}
\ No newline at end of file
+}
+/// Removes what `add_footer` appended, leaving only the translated text.
+pub fn strip_footer<'a>(text: &'a str, lang: &Language) -> &'a str {
+    let text = text.trim_end();
+    let text = text.strip_suffix(lang.footer).unwrap_or(text).trim_end();
+
+    match text.rsplit_once('\n') {
+        Some((body, hashtags)) if hashtags.starts_with('#') => body.trim_end(),
+        _ => text,
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum HtmlSegment<'a> {
+    Tag(&'a str),
+    Text(&'a str),
+}
+
+/// Splits HTML into tags and the text runs between them.
+pub fn split_html(html: &str) -> Vec<HtmlSegment<'_>> {
+    let mut segments = Vec::new();
+    let mut last = 0;
+
+    for tag in TAG_REGEX.find_iter(html) {
+        if tag.start() > last {
+            segments.push(HtmlSegment::Text(&html[last..tag.start()]));
+        }
+        segments.push(HtmlSegment::Tag(tag.as_str()));
+        last = tag.end();
+    }
+
+    if last < html.len() {
+        segments.push(HtmlSegment::Text(&html[last..]));
+    }
+
+    segments
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn unescape_html(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Translates every text run of `html` on its own and puts the original tags back around them,
+/// for engines that only understand plain text.
+pub async fn translate_html_runs<F, Fut>(html: &str, mut translate_run: F) -> Result<String>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output=Result<String>>,
+{
+    let mut translated = String::with_capacity(html.len());
+
+    for segment in split_html(html) {
+        match segment {
+            HtmlSegment::Tag(tag) => translated.push_str(tag),
+            HtmlSegment::Text(text) => {
+                let text = unescape_html(text);
+                let run = text.trim();
+
+                if !run.chars().any(char::is_alphabetic) {
+                    translated.push_str(&escape_html(&text));
+                    continue;
+                }
+
+                let leading = &text[..text.len() - text.trim_start().len()];
+                let trailing = &text[text.trim_end().len()..];
+
+                translated.push_str(leading);
+                translated.push_str(&escape_html(&translate_run(run.to_string()).await?));
+                translated.push_str(trailing);
+            }
+        }
+    }
+
+    Ok(translated)
+}
+
+
+#[derive(Error, Debug)]
+pub enum HtmlError {
+    #[error("Malformed tag `{0}`")]
+    MalformedTag(String),
+
+    #[error("Unterminated tag in `{0}`")]
+    UnterminatedTag(String),
+}
+
+/// Rebuilds a supported opening tag with only its allowed attributes, `None` if it has to be dropped.
+fn sanitize_open_tag(name: &str, attributes: &str) -> Option<String> {
+    let (_, allowed) = SUPPORTED_TAGS.iter().find(|(tag, _)| *tag == name)?;
+
+    let mut tag = format!("<{name}");
+    for attribute in ATTRIBUTE_REGEX.captures_iter(attributes) {
+        if allowed.contains(&&attribute[1]) {
+            match attribute.get(2) {
+                Some(value) => tag.push_str(&format!(" {}=\"{}\"", &attribute[1], value.as_str())),
+                None => tag.push_str(&format!(" {}", &attribute[1])),
+            }
+        }
+    }
+    tag.push('>');
+
+    let required_missing = match name {
+        "a" => !tag.contains("href="),
+        "tg-emoji" => !tag.contains("emoji-id="),
+        _ => false,
+    };
+
+    (!required_missing).then_some(tag)
+}
+
+/// Checks translated HTML against Telegram's tag set: unsupported tags and attributes are dropped,
+/// misnested tags are closed and reopened, stray closing tags removed and open tags closed at the end.
+pub fn repair_html(html: &str) -> Result<String, HtmlError> {
+    let mut repaired = String::with_capacity(html.len());
+    let mut open: Vec<(String, String)> = Vec::new();
+
+    for segment in split_html(html) {
+        let tag = match segment {
+            HtmlSegment::Text(text) if text.contains('<') => return Err(HtmlError::UnterminatedTag(text.to_string())),
+            HtmlSegment::Text(text) => {
+                repaired.push_str(&escape_html(&unescape_html(text)));
+                continue;
+            }
+            HtmlSegment::Tag(tag) => tag,
+        };
+
+        let parts = TAG_PARTS_REGEX.captures(tag)
+            .ok_or_else(|| HtmlError::MalformedTag(tag.to_string()))?;
+        let name = parts[2].to_lowercase();
+
+        // Telegram doesn't parse entities inside code blocks or links inside links.
+        let in_code = open.iter().any(|(open, _)| open == "code" || open == "pre");
+        let in_link = open.iter().any(|(open, _)| open == "a");
+
+        if parts[1].is_empty() {
+            if in_code && !(name == "code" && open.last().is_some_and(|(open, _)| open == "pre")) || in_link && name == "a" {
+                continue;
+            }
+            if let Some(open_tag) = sanitize_open_tag(&name, &parts[3]) {
+                repaired.push_str(&open_tag);
+                open.push((name, open_tag));
+            }
+            continue;
+        }
+
+        let Some(position) = open.iter().rposition(|(open, _)| *open == name) else {
+            continue;
+        };
+
+        let reopen = open.split_off(position + 1);
+        for (inner, _) in reopen.iter().rev() {
+            repaired.push_str(&format!("</{inner}>"));
+        }
+        repaired.push_str(&format!("</{name}>"));
+        open.pop();
+
+        for (inner, open_tag) in reopen {
+            repaired.push_str(&open_tag);
+            open.push((inner, open_tag));
+        }
+    }
+
+    for (name, _) in open.iter().rev() {
+        repaired.push_str(&format!("</{name}>"));
+    }
+
+    Ok(repaired)
+}
+
+/// Drops every tag and keeps only the escaped text, for when `repair_html` gives up.
+pub fn strip_html(html: &str) -> String {
+    split_html(html).into_iter()
+        .filter_map(|segment| match segment {
+            HtmlSegment::Text(text) => Some(escape_html(&unescape_html(text))),
+            HtmlSegment::Tag(_) => None,
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use tokio::runtime::Runtime;
+
+    use super::*;
+
+    #[test]
+    fn test_split_html() {
+        assert_eq!(
+            split_html("<b>Eilmeldung:</b> Angriff auf <a href=\"https://t.me\">Kiew</a>"),
+            vec![
+                HtmlSegment::Tag("<b>"),
+                HtmlSegment::Text("Eilmeldung:"),
+                HtmlSegment::Tag("</b>"),
+                HtmlSegment::Text(" Angriff auf "),
+                HtmlSegment::Tag("<a href=\"https://t.me\">"),
+                HtmlSegment::Text("Kiew"),
+                HtmlSegment::Tag("</a>"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_translate_html_runs_keeps_markup() {
+        // Arrange
+        let html = "<b>Eilmeldung:</b> Angriff &amp; Abwehr <tg-spoiler>geheim</tg-spoiler> 🇺🇦";
+
+        // Act
+        let translated = Runtime::new().unwrap().block_on(translate_html_runs(html, |run| async move {
+            Ok(run.to_uppercase())
+        }));
+
+        // Assert
+        assert_eq!(
+            translated.unwrap(),
+            "<b>EILMELDUNG:</b> ANGRIFF &amp; ABWEHR <tg-spoiler>GEHEIM</tg-spoiler> 🇺🇦"
+        );
+    }
+
+    #[test]
+    fn test_repair_html_keeps_valid_html() {
+        let html = "<b>Eilmeldung:</b> <a href=\"https://t.me/MilitaerNews\">Kiew</a> <tg-spoiler>x</tg-spoiler>";
+        assert_eq!(repair_html(html).unwrap(), html);
+    }
+
+    #[test]
+    fn test_repair_html_fixes_nesting_and_balance() {
+        // Arrange
+        let html = "<b>fett <i>beides</b> kursiv</i> </u><s>offen";
+
+        // Act
+        let repaired = repair_html(html).unwrap();
+
+        // Assert
+        assert_eq!(repaired, "<b>fett <i>beides</i></b><i> kursiv</i> <s>offen</s>");
+    }
+
+    #[test]
+    fn test_repair_html_drops_unsupported_tags_and_attributes() {
+        let html = "<p>Text</p> <a>kein Link</a> <b class=\"x\">fett</b> & mehr";
+        assert_eq!(repair_html(html).unwrap(), "Text kein Link <b>fett</b> &amp; mehr");
+    }
+
+    #[test]
+    fn test_repair_html_fails_on_unterminated_tag() {
+        assert!(repair_html("<b>fett</b> <a href=\"https://t.me").is_err());
+        assert_eq!(strip_html("<b>fett</b> <a href=\"https://t.me"), "fett &lt;a href=&quot;https://t.me");
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use anyhow::{anyhow, bail, Result};
+use deepl::{DeepLApi, EntriesFormat, GlossaryLanguageCode};
+use grammers_client::types::Message;
+use lazy_static::lazy_static;
+use tracing::{info, warn};
+
+use crate::lang::{find_language, target_languages, LangKey, Language};
+use crate::config::TranslationConfig;
+
+lazy_static! {
+    /// DeepL glossary ids by API key index and language code.
+    static ref GLOSSARY_IDS: RwLock<HashMap<(usize, String), String>> = RwLock::new(HashMap::new());
+}
+
+fn glossary_name(lang: &Language) -> String {
+    format!("mn-rs-de-{}", lang.lang_key.code())
+}
+
+fn glossary_lang(lang_key: &LangKey) -> Option<GlossaryLanguageCode> {
+    match lang_key {
+        LangKey::DE => Some(GlossaryLanguageCode::DE),
+        LangKey::EN => Some(GlossaryLanguageCode::EN),
+        LangKey::AR => None,
+    }
+}
+
+/// Glossaries are edited at runtime, so they are read from disk instead of being embedded like the flags.
+/// The directory has to survive restarts and redeployments, or the edits made from the log group are lost.
+fn glossary_path(lang: &Language, config: &TranslationConfig) -> PathBuf {
+    PathBuf::from(&config.glossary_dir).join(lang.lang_key.code()).join("glossary.json")
+}
+
+/// Returns `None` if the language has no glossary file.
+pub fn load_entries(lang: &Language, config: &TranslationConfig) -> Result<Option<BTreeMap<String, String>>> {
+    match std::fs::read(glossary_path(lang, config)) {
+        Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn save_entries(lang: &Language, entries: &BTreeMap<String, String>, config: &TranslationConfig) -> Result<()> {
+    let path = glossary_path(lang, config);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(entries)?)?;
+    Ok(())
+}
+
+pub fn glossary_id(key_index: usize, lang_key: &LangKey) -> Option<String> {
+    GLOSSARY_IDS.read().unwrap()
+        .get(&(key_index, lang_key.code()))
+        .cloned()
+}
+
+/// Replaces the DeepL glossary of `lang` on every API key with the entries from its file.
+/// DeepL glossaries can't be edited, so a new one is created and the old one deleted.
+/// Without a file the existing glossary is kept, a missing directory mustn't wipe what DeepL has.
+async fn sync_language(lang: &Language, config: &TranslationConfig) -> Result<()> {
+    let target_lang = glossary_lang(&lang.lang_key)
+        .ok_or_else(|| anyhow!("DeepL has no glossaries into {}", lang.lang_key))?;
+    let name = glossary_name(lang);
+    let Some(entries) = load_entries(lang, config)? else {
+        warn!("{} is missing, keeping the DeepL glossary {name}", glossary_path(lang, config).display());
+        return keep_existing(lang, &name, config).await;
+    };
+
+    for (i, key) in config.deepl_keys.iter().enumerate() {
+        let api = DeepLApi::with(key).new();
+        let outdated = api.list_all_glossaries().await?
+            .glossaries.into_iter()
+            .filter(|glossary| glossary.name == name)
+            .collect::<Vec<_>>();
+
+        // The new glossary is in place before the old one goes, so concurrent translations never use a deleted id.
+        if entries.is_empty() {
+            GLOSSARY_IDS.write().unwrap().remove(&(i, lang.lang_key.code()));
+        } else {
+            let glossary = api.create_glossary(&name)
+                .source_lang(GlossaryLanguageCode::DE)
+                .target_lang(target_lang.clone())
+                .entries(entries.iter())
+                .format(EntriesFormat::TSV)
+                .send()
+                .await?;
+
+            GLOSSARY_IDS.write().unwrap().insert((i, lang.lang_key.code()), glossary.glossary_id);
+        }
+
+        for glossary in outdated {
+            api.delete_glossary(glossary.glossary_id).await?;
+        }
+    }
+
+    info!("Synced DeepL glossary {name} with {} entries", entries.len());
+    Ok(())
+}
+
+/// Uses the glossaries DeepL already has for `lang` without touching them.
+async fn keep_existing(lang: &Language, name: &str, config: &TranslationConfig) -> Result<()> {
+    for (i, key) in config.deepl_keys.iter().enumerate() {
+        let existing = DeepLApi::with(key).new()
+            .list_all_glossaries().await?
+            .glossaries.into_iter()
+            .find(|glossary| glossary.name == name);
+
+        if let Some(glossary) = existing {
+            GLOSSARY_IDS.write().unwrap().insert((i, lang.lang_key.code()), glossary.glossary_id);
+        }
+    }
+    Ok(())
+}
+
+/// Uploads the glossaries of all DeepL languages, called once on startup.
+pub async fn sync_all(config: &TranslationConfig) -> Result<()> {
+    for lang in target_languages().iter().filter(|lang| lang.lang_key_deepl.is_some()) {
+        sync_language(lang, config).await?;
+    }
+    Ok(())
+}
+
+/// `/glossary_add <lang> <source> = <target>` and `/glossary_remove <lang> <source>` from the log group.
+pub async fn handle_command(message: &Message, config: &TranslationConfig) -> Result<()> {
+    let text = message.text();
+    let (command, args) = text.split_once(' ').unwrap_or((text, ""));
+    let (lang, term) = args.trim().split_once(' ')
+        .ok_or_else(|| anyhow!("Usage: /glossary_add <lang> <source> = <target> or /glossary_remove <lang> <source>"))?;
+    let lang = find_language(lang).ok_or_else(|| anyhow!("Unknown language `{lang}`"))?;
+
+    let mut entries = load_entries(lang, config)?.unwrap_or_default();
+    let reply = match command {
+        "/glossary_add" => {
+            let (source, target) = term.split_once('=')
+                .ok_or_else(|| anyhow!("Usage: /glossary_add <lang> <source> = <target>"))?;
+            entries.insert(source.trim().to_string(), target.trim().to_string());
+            format!("📖 Added `{}` → `{}`", source.trim(), target.trim())
+        }
+        "/glossary_remove" => match entries.remove(term.trim()) {
+            Some(target) => format!("📖 Removed `{}` → `{target}`", term.trim()),
+            None => bail!("`{}` is not in the {} glossary", term.trim(), lang.lang_key),
+        },
+        _ => bail!("Unknown glossary command `{command}`"),
+    };
+
+    save_entries(lang, &entries, config)?;
+    sync_language(lang, config).await?;
+
+    message.reply(reply).await?;
+    Ok(())
+}
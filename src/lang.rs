@@ -3,10 +3,11 @@ use std::fmt::{self, Debug, Display, Formatter};
 
 pub type DeeplLang = deepl::Lang;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LangKey {
     DE,
     EN,
+    AR,
 }
 
 impl Display for LangKey {
@@ -16,6 +17,31 @@ impl Display for LangKey {
     }
 }
 
+impl LangKey {
+    /// Lowercase code used for resource folders and database rows.
+    pub fn code(&self) -> String {
+        self.to_string().to_lowercase()
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code.to_lowercase().as_str() {
+            "de" => Some(Self::DE),
+            "en" => Some(Self::EN),
+            "ar" => Some(Self::AR),
+            _ => None,
+        }
+    }
+
+    /// DeepL source languages have no regional variants.
+    pub fn deepl_source(&self) -> DeeplLang {
+        match self {
+            Self::DE => DeeplLang::DE,
+            Self::EN => DeeplLang::EN,
+            Self::AR => DeeplLang::AR,
+        }
+    }
+}
+
 pub struct Language {
     pub lang_key: LangKey,
     pub channel_id: i64,
@@ -56,6 +82,21 @@ pub const LANGUAGES : [Language; 2] = [
 ];
 
 
+/// Languages the source posts get translated into, i.e. everything but the source language.
+pub fn target_languages() -> &'static [Language] {
+    let languages: &'static [Language] = &LANGUAGES;
+    &languages[1..]
+}
+
+pub fn find_language(code: &str) -> Option<&'static Language> {
+    target_languages().iter().find(|lang| lang.lang_key.code() == code)
+}
+
+pub fn find_language_by_channel(channel_id: i64) -> Option<&'static Language> {
+    target_languages().iter().find(|lang| lang.channel_id == channel_id)
+}
+
+
 /*
  const LANGUAGES_real: [Language; 2] = [
     Language {
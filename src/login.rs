@@ -0,0 +1,117 @@
+use std::fs::{DirBuilder, Permissions};
+use std::io::IsTerminal;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use grammers_client::{Client, SignInError};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixListener;
+use tokio::time::sleep;
+use tracing::info;
+
+use crate::config::{LoginConfig, Secret, TelegramConfig};
+use crate::util::{prompt, prompt_hidden};
+
+/// Creates the directory of the socket only the owner may enter, or checks that an existing one is private.
+/// Restricting the socket itself after `bind` would leave a window in which anybody could connect.
+fn private_dir(socket: &Path) -> Result<()> {
+    let dir = socket.parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .ok_or_else(|| anyhow!("Login socket {} needs a directory of its own", socket.display()))?;
+
+    DirBuilder::new().recursive(true).mode(0o700).create(dir)
+        .with_context(|| format!("Creating {} failed", dir.display()))?;
+
+    let mode = std::fs::metadata(dir)?.permissions().mode();
+    if mode & 0o077 != 0 {
+        bail!("{} is accessible to others (mode {:o}), the login socket needs a private directory", dir.display(), mode & 0o777);
+    }
+    Ok(())
+}
+
+/// Writes `question` to the first client connecting to the socket and reads its answer,
+/// e.g. with `socat - UNIX-CONNECT:<socket>`. Only the owner may connect.
+async fn ask_socket(path: &Path, question: &str) -> Result<Secret> {
+    private_dir(path)?;
+    let _ = std::fs::remove_file(path);
+    let listener = UnixListener::bind(path).with_context(|| format!("Binding {} failed", path.display()))?;
+    std::fs::set_permissions(path, Permissions::from_mode(0o600))?;
+
+    info!("Waiting on {} for: {question}", path.display());
+    let (stream, _) = listener.accept().await?;
+    let (reader, mut writer) = stream.into_split();
+    writer.write_all(question.as_bytes()).await?;
+
+    let mut answer = String::new();
+    BufReader::new(reader).read_line(&mut answer).await?;
+
+    drop(listener);
+    let _ = std::fs::remove_file(path);
+    Ok(Secret::new(&answer))
+}
+
+/// Polls `path` until somebody writes the code into it, the file is only read after the code was requested.
+async fn wait_for_code(path: &Path) -> Result<Secret> {
+    info!("Waiting for the login code in {}", path.display());
+    loop {
+        match tokio::fs::read_to_string(path).await {
+            Ok(code) if !code.trim().is_empty() => {
+                let _ = std::fs::remove_file(path);
+                return Ok(Secret::new(code.trim()));
+            }
+            Ok(_) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e).with_context(|| format!("Reading the login code from {} failed", path.display())),
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Takes the configured answer, then asks on the login socket and only then on the terminal.
+async fn ask(configured: &Option<Secret>, login: &LoginConfig, question: &str, hidden: bool) -> Result<Secret> {
+    if let Some(answer) = configured {
+        return Ok(answer.clone());
+    }
+    if let Some(socket) = &login.socket {
+        return ask_socket(socket, question).await;
+    }
+    if !std::io::stdin().is_terminal() {
+        bail!("No terminal to ask \"{}\" on, configure the answer or a login socket", question.trim_end());
+    }
+
+    let answer = if hidden { prompt_hidden(question)? } else { prompt(question)? };
+    Ok(Secret::new(&answer))
+}
+
+pub async fn sign_in(client: &Client, telegram: &TelegramConfig) -> Result<()> {
+    println!("Signing in...");
+    if let Some(path) = &telegram.login.code_file {
+        // A code left over from an earlier login is no longer valid.
+        let _ = std::fs::remove_file(path);
+    }
+
+    let token = client.request_login_code(&telegram.phone).await?;
+    let code = match &telegram.login.code_file {
+        Some(path) => wait_for_code(path).await?,
+        None => ask(&None, &telegram.login, "Enter the code you received: ", false).await?,
+    };
+
+    match client.sign_in(&token, code.expose()).await {
+        Err(SignInError::PasswordRequired(password_token)) => {
+            let hint = password_token.hint().unwrap_or("No password hint");
+            let question = format!("Enter the password (hint {hint}): ");
+            let password = ask(&telegram.login.password, &telegram.login, &question, true).await?;
+
+            client
+                .check_password(password_token, password.expose())
+                .await?;
+        }
+        Ok(_) => (),
+        Err(e) => return Err(e).context("Sign in failed"),
+    };
+
+    println!("Signed in!");
+    Ok(())
+}
@@ -1,114 +1,241 @@
 #![feature(async_closure)]
 
-use std::ops::ControlFlow;
+use std::io;
 use std::sync::atomic::{AtomicI64, Ordering};
 use std::time::{Duration, SystemTime};
 
-use anyhow::{anyhow, Context, Error, Result};
+use anyhow::{anyhow, bail, Context, Error, Result};
 use dotenv::dotenv;
-use grammers_client::{Client, Config, InitParams, SignInError, Update};
-use grammers_client::types::{Channel, Group, Message};
-use grammers_mtsender::{InvocationError, ReconnectionPolicy};
-use grammers_session::{PackedChat, PackedType, Session};
+use grammers_client::{Client, Config as ClientConfig, InitParams, Update};
+use grammers_client::types::Message;
+use grammers_session::PackedChat;
+use grammers_tl_types::functions::messages::SendMessage;
 use grammers_tl_types::{enums, types};
-use grammers_tl_types::enums::{Chat, InputMedia, InputMessage, Peer, Updates};
-use grammers_tl_types::enums::messages::Chats;
-use grammers_tl_types::functions::channels::GetChannels;
-use grammers_tl_types::functions::messages;
-use grammers_tl_types::functions::messages::{GetChats, SendMessage};
-use grammers_tl_types::types::{InputChannel, InputPeerChat, InputReplyToMessage, PeerChat};
 use sqlx::PgPool;
 use sqlx::postgres::PgPoolOptions;
-use tracing::{error, warn};
-
-use crate::db::Post;
-use crate::formatting::add_footer;
-use crate::lang::LANGUAGES;
+use tokio::task::AbortHandle;
+use tokio::time::sleep;
+use tracing::{error, trace, warn};
+
+use crate::cli::Command;
+use crate::config::{Config, TelegramConfig};
+use crate::dispatch::Dispatcher;
+use crate::entities::Rendered;
+use crate::lang::{find_language, find_language_by_channel, LangKey};
 use crate::translation::translate;
-use crate::util::prompt;
 
+mod config;
 mod db;
 mod translation;
 mod util;
 mod lang;
 mod formatting;
+mod send;
+mod outbox;
+mod recovery;
+mod backfill;
+mod shadow;
+mod review;
+mod memory;
+mod glossary;
+mod terms;
+mod entities;
+mod detect;
+mod routing;
+mod tags;
+mod peers;
+mod cli;
+mod check;
+mod login;
+mod session;
+mod shutdown;
+mod reconnect;
+mod dispatch;
+
+
+/// Source of the default route, used when there is no routes file.
+const SOURCE_CHANNEL: i64 = 1391125365;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
 
+    tracing_subscriber::fmt().with_writer(std::io::stderr).init();
 
-const SESSION_FILE: &str = "mn-rs.session";
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let command = cli::parse(&args)?;
 
-/// note that this can contain any value you need, in this case, its empty
-struct MyPolicy;
+    let config = Config::load(command.scope())?;
 
-impl ReconnectionPolicy for MyPolicy {
-    ///this is the only function you need to implement,
-    /// it gives you the attempted reconnections, and `self` in case you have any data in your struct.
-    /// you should return a [`ControlFlow`] which can be either `Break` or `Continue`, break will **NOT** attempt a reconnection,
-    /// `Continue` **WILL** try to reconnect after the given **Duration**.
-    ///
-    /// in this example we are simply sleeping exponentially based on the attempted count,
-    /// however this is not a really good practice for production since we are just doing 2 raised to the power of attempts and that will result to massive
-    /// numbers very soon, just an example!
-    fn should_retry(&self, attempts: usize) -> ControlFlow<(), Duration> {
-        let duration = u64::pow(2, attempts as _);
-        ControlFlow::Continue(Duration::from_millis(duration))
+    match command {
+        Command::Run => run(&config).await,
+        Command::Login => login(&config).await,
+        Command::Check => {
+            let db_pool = setup_database(&config).await?;
+            let client = signed_in_client(&config.telegram, &db_pool).await?;
+            peers::init(&client, &db_pool, &config).await?;
+            check::run(&client, &config).await
+        }
+        Command::Backfill(args) => {
+            let (client, db_pool) = start(&config).await?;
+            tokio::spawn(shutdown::listen());
+            backfill::run(&client, &db_pool, &config, &args).await
+        }
+        Command::Translate { lang, source_lang } => translate_stdin(&lang, source_lang.as_deref(), &config).await,
+        Command::Export(path) => memory::export_tmx(&path, &setup_database(&config).await?).await,
+        Command::Import(path) => memory::import_tmx(&path, &setup_database(&config).await?).await,
     }
 }
 
+async fn run(config: &Config) -> Result<()> {
+    let (client, db_pool) = start(config).await?;
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    dotenv().ok();
+    // Before the gap recovery, so a SIGTERM during it isn't lost.
+    tokio::spawn(shutdown::listen());
 
-    tracing_subscriber::fmt::init();
+    if let Err(err) = recovery::recover_gap(&client, &db_pool, config).await {
+        let _ = handle_error(&client, config, err.context("Gap recovery failed")).await.map_err(|e| error!("⚠️ Failed to handle error: {e:?}"));
+    }
 
-    let db_pool = setup_database().await?;
+    let mut worker = tokio::spawn(outbox::run_worker(client.clone(), db_pool.clone(), config.clone()));
+    tokio::spawn(session::save_periodically(client.clone(), db_pool.clone()));
+    tokio::spawn(reconnect::supervise(client.clone(), config.clone()));
 
-    let client = setup_telegram_client().await?;
+    let dispatcher = Dispatcher::start(&client, &db_pool, config);
+    let lanes = dispatcher.abort_handles();
+    let mut result = Ok(());
+    let stopped = async {
+        result = receive_updates(&client, &dispatcher).await;
 
-    if !client.is_authorized().await? {
-        authenticate_user(&client).await?;
+        // Also stops the worker if the updates failed.
+        shutdown::request();
+        dispatcher.finish().await;
+        let _ = (&mut worker).await;
+    };
+
+    // The deadline starts with the shutdown request, so it covers the update being dispatched as well.
+    let deadline = Duration::from_secs(config.shutdown_timeout_secs);
+    let expired = async {
+        shutdown::requested().await;
+        sleep(deadline).await;
+    };
+
+    let drained = tokio::select! {
+        _ = stopped => true,
+        _ = expired => false,
+    };
+    if !drained {
+        warn!("Queued updates and in-flight jobs did not finish within {deadline:?}, they resume on the next start");
+        // The lanes and the worker mustn't outlive the pool, or they would fail halfway through a post.
+        lanes.iter().for_each(AbortHandle::abort);
+        worker.abort();
+        outbox::requeue_interrupted(&db_pool).await;
     }
 
-    while let update = client.next_update().await? {
-        error!("UPD :: {update:?}");
-        if let Err(err) = process_update(update, &client, &db_pool).await {
-            let _ = handle_error(&client, err).await.map_err(|e| error!("⚠️ Failed to handle error: {e:?}"));
-        }
+    session::save(&client, &db_pool).await?;
+    db_pool.close().await;
+    result
+}
+
+/// Hands the updates to the dispatcher until a shutdown is requested.
+async fn receive_updates(client: &Client, dispatcher: &Dispatcher) -> Result<()> {
+    loop {
+        let update = tokio::select! {
+            update = client.next_update() => update?,
+            _ = shutdown::requested() => return Ok(()),
+        };
+
+        trace!("Received update {update:?}");
+        // Not raced against the shutdown, the update is already taken from Telegram.
+        dispatcher.dispatch(update).await;
     }
+}
 
-    Ok(())
+/// Connects and prepares everything needed to translate and publish posts.
+async fn start(config: &Config) -> Result<(Client, PgPool)> {
+    let db_pool = setup_database(config).await?;
+
+    let client = signed_in_client(&config.telegram, &db_pool).await?;
+    send::set_max_flood_wait(config.max_flood_wait_secs);
+
+    peers::init(&client, &db_pool, config).await?;
+
+    shadow::init(&client, &config.shadow, &db_pool).await?;
+    entities::detect_custom_emoji_support(&client, config.translation.custom_emoji).await?;
+
+    if let Err(err) = glossary::sync_all(&config.translation).await {
+        let _ = handle_error(&client, config, err.context("Glossary sync failed")).await.map_err(|e| error!("⚠️ Failed to handle error: {e:?}"));
+    }
+
+    Ok((client, db_pool))
 }
 
-async fn handle_error(client: &Client, err: Error) -> Result<()> {
-    let packed_chat = PackedChat {
-        ty: PackedType::Megagroup,
-        id: getenv!("LOG_GROUP",i64),
-        access_hash: Some(-8404657102874664500),
-    };
+async fn login(config: &Config) -> Result<()> {
+    let db_pool = setup_database(config).await?;
+    let client = setup_telegram_client(&config.telegram, &db_pool).await?;
+    if client.is_authorized().await? {
+        println!("Already signed in.");
+        return Ok(());
+    }
+    login::sign_in(&client, &config.telegram).await?;
+    save_session_or_set_sign_out(&client, &db_pool).await
+}
 
+async fn signed_in_client(telegram: &TelegramConfig, db_pool: &PgPool) -> Result<Client> {
+    let client = setup_telegram_client(telegram, db_pool).await?;
+    if !client.is_authorized().await? {
+        bail!("Not signed in, run `mn-rs login` first");
+    }
+    Ok(client)
+}
+
+/// `translate`, which runs stdin through the translation engines without the translation memory.
+async fn translate_stdin(lang: &str, source_lang: Option<&str>, config: &Config) -> Result<()> {
+    let lang = find_language(lang).ok_or_else(|| anyhow!("Unknown language `{lang}`"))?;
+    let source_lang = source_lang
+        .map(|code| LangKey::from_code(code).ok_or_else(|| anyhow!("Unknown source language `{code}`")))
+        .transpose()?;
+
+    let text = io::read_to_string(io::stdin()).context("Reading stdin failed")?;
+    let source_lang = detect::source_language(source_lang, &text);
+
+    let translation = translate(&text, &source_lang, &lang.lang_key, &lang.lang_key_deepl, &config.translation).await?;
+    println!("{translation}");
+    Ok(())
+}
+
+async fn handle_error(client: &Client, config: &Config, err: Error) -> Result<()> {
     error!("{err:?}");
-    client.send_message(packed_chat, format!("⚠️ {err}"))
+    let text = format!("⚠️ {err}");
+    peers::with_peer(client, config.log_group, |chat| send::send_message(client, chat, text.as_str()))
         .await?;
 
     Ok(())
 }
 
-async fn setup_database() -> Result<PgPool> {
-    PgPoolOptions::new()
+async fn setup_database(config: &Config) -> Result<PgPool> {
+    let db_pool = PgPoolOptions::new()
         .max_connections(5)
-        .connect(&getenv!("DATABASE_URL"))
+        .connect(&config.database_url)
         .await
-        .context("DB connection failed")
+        .context("DB connection failed")?;
+
+    sqlx::migrate!("./migrations")
+        .run(&db_pool)
+        .await
+        .context("DB migration failed")?;
+
+    Ok(db_pool)
 }
 
-async fn setup_telegram_client() -> Result<Client> {
+async fn setup_telegram_client(telegram: &TelegramConfig, db_pool: &PgPool) -> Result<Client> {
     println!("Connecting to Telegram...");
-    let client = Client::connect(Config {
-        session: Session::load_file_or_create(SESSION_FILE)?,
-        api_id: getenv!("TG_ID", i32),
-        api_hash: getenv!("TG_HASH"),
+    let client = Client::connect(ClientConfig {
+        session: session::load(db_pool).await?,
+        api_id: telegram.api_id,
+        api_hash: telegram.api_hash.clone(),
         params: InitParams {
-            reconnection_policy: &MyPolicy,
+            reconnection_policy: &reconnect::Backoff,
             catch_up: true,
             ..Default::default()
         },
@@ -118,33 +245,8 @@ async fn setup_telegram_client() -> Result<Client> {
     Ok(client)
 }
 
-async fn authenticate_user(client: &Client) -> Result<()> {
-    println!("Signing in...");
-    let phone = getenv!("TG_MOBILE_NUMBER");
-    let token = client.request_login_code(&phone).await?;
-    let code = prompt("Enter the code you received: ")?;
-    let signed_in = client.sign_in(&token, &code).await;
-    match signed_in {
-        Err(SignInError::PasswordRequired(password_token)) => {
-            // Note: this `prompt` method will echo the password in the console.
-            //       Real code might want to use a better way to handle this.
-            let hint = password_token.hint().unwrap_or("No password hint");
-            let prompt_message = format!("Enter the password (hint {}): ", &hint);
-            let password = prompt(prompt_message.as_str())?;
-
-            client
-                .check_password(password_token, password.trim())
-                .await?;
-        }
-        Ok(_) => (),
-        Err(e) => panic!("{}", e),
-    };
-    println!("Signed in!");
-    save_session_or_set_sign_out(client).await
-}
-
-async fn save_session_or_set_sign_out(client: &Client) -> Result<()> {
-    match client.session().save_to_file(SESSION_FILE) {
+async fn save_session_or_set_sign_out(client: &Client, db_pool: &PgPool) -> Result<()> {
+    match session::save(client, db_pool).await {
         Ok(_) => println!("Session saved."),
         Err(e) => {
             error!("NOTE: failed to save the session, will sign out when done: {e}");
@@ -155,15 +257,21 @@ async fn save_session_or_set_sign_out(client: &Client) -> Result<()> {
 }
 
 
-async fn process_update(update: Update, client: &Client, db_pool: &PgPool) -> Result<()> {
-    warn!("UPD PRC :: {update:?}");
+async fn process_update(update: Update, client: &Client, db_pool: &PgPool, config: &Config) -> Result<()> {
+    trace!("Processing update {update:?}");
     match update {
         Update::NewMessage(message) if !message.outgoing() && message.text() == "test" =>
             pong(&message).await,
-        Update::NewMessage(message) if !message.outgoing() && message.chat().id() == 1391125365 && message.media().is_some() =>
-            handle_media(&message, client, db_pool).await,
-        Update::NewMessage(message) if !message.outgoing() && message.chat().id() == 1391125365 =>
-            handle_text(&message, client, db_pool).await,
+        Update::NewMessage(message) if !message.outgoing() && message.chat().id() == config.log_group && message.text() == "/retry" =>
+            retry_failed_jobs(&message, db_pool).await,
+        Update::NewMessage(message) if !message.outgoing() && message.chat().id() == config.log_group && message.text().starts_with("/glossary_") =>
+            glossary::handle_command(&message, &config.translation).await,
+        Update::NewMessage(message) if !message.outgoing() && message.chat().id() == config.log_group && message.reply_to_message_id().is_some() =>
+            review::handle_reply(&message, client, db_pool, config).await,
+        Update::NewMessage(message) if !message.outgoing() && config.route_for(message.chat().id()).is_some() =>
+            outbox::enqueue(&message, db_pool, config).await,
+        Update::MessageEdited(message) if find_language_by_channel(message.chat().id()).is_some() =>
+            memory::learn_from_edit(&message, client, db_pool).await,
         _ => Ok(()),
     }
 }
@@ -176,152 +284,68 @@ async fn pong(message: &Message) -> Result<()> {
     Ok(())
 }
 
-async fn handle_text(message: &Message, client:&Client, db_pool: &PgPool) -> Result<()> {
-    for lang in &LANGUAGES[1..] {
-        let text = translate(
-            &*message.html_text(),
-            &lang.lang_key,
-            &lang.lang_key_deepl,
-        ).await?;
-
-
-        let formatted_text = add_footer(text, &lang)?;
-
-
-
-
-        let packed_chat = PackedChat {
-            ty: PackedType::Megagroup,
-            id: getenv!("LOG_GROUP",i64),
-            access_hash: Some(-8404657102874664500),
-        };
-
-
-
-        client.send_message(packed_chat, format!("TRANS PACK {formatted_text}"))
-            .await?;
-
-        let packed_channel = PackedChat {
-            ty: PackedType::Broadcast,
-            id:  lang.channel_id,
-            access_hash: Some(2889309565767224873),
-        };
-
-        client.send_message(packed_channel, format!("TRANS LANG {formatted_text}"))
-            .await?;
-
-        let msg = message.respond(formatted_text).await?;
-
-        Post::insert("li".parse().unwrap(), msg.id(), db_pool).await?;
-    }
-
-
-    Ok(())
-}
-
-
-
-
-async fn handle_media(message: &Message, client: &Client, db_pool: &PgPool)  -> Result<()> {
-    for lang in &LANGUAGES[1..] {
-        let text = translate(
-            &*message.html_text(),
-            &lang.lang_key,
-            &lang.lang_key_deepl,
-        ).await?;
-
-
-        let formatted_text = add_footer(text, &lang)?;
-
-
-
-
-        let packed_channel = PackedChat {
-            ty: PackedType::Broadcast,
-            id:  lang.channel_id,
-            access_hash: Some(2889309565767224873),
-        };
-
-        let msg = message.clone();
-
-// let uu = message.copy(packed_channel.clone(), Some(formatted_text.clone())).await;
-
-    //    error!("uu: {uu:?}");
-
-       let msg2 = copy_message(&msg, &client, Some(formatted_text.clone()),  lang.channel_id,).await?;
-
-//let med = message.media().unwrap();
-
-     //   let ip = InputMedia::from( message.media().unwrap());
-
-
-  //   let rr =  client.send_message(packed_channel, ).await?;
-
-   //    let _ = message.forward_to(&packed_channel).await;
-
-        client.send_message(packed_channel, format!("TRANS LANG {formatted_text}"))
-            .await?;
-
-        let msg = message.respond(formatted_text).await?;
-
-        Post::insert("li".parse().unwrap(), msg.id(), db_pool).await?;
-    }
-
-
+async fn retry_failed_jobs(message: &Message, db_pool: &PgPool) -> Result<()> {
+    let count = outbox::retry_failed(db_pool).await?;
+    message.respond(format!("🔁 Requeued {count} failed jobs")).await?;
     Ok(())
 }
 
-
-
-async fn copy_message(message:&Message, client:&Client ,   caption: Option<String>, chat_id: i64 )-> Result<()>{
-
-  /*  let chat = client  .invoke(&GetChats {
-        id: vec![chat_id],
-    })
-        .await?;
-
-   */
-
-    let chat =  client.invoke(&GetChannels {
-        id: vec![enums::InputChannel::Channel(InputChannel {
-            channel_id: chat_id,
-            access_hash: 0,
-        })]
-    }).await? .chats().get(0).unwrap().clone();
-
-  let chan =   types::InputPeerChannel {
-        channel_id: chat.id(),
-        access_hash: 2889309565767224873,
-    }
-        .into();
-
+/// Returns the id of the copy, `0` if Telegram didn't report it.
+async fn copy_message(message:&Message, client:&Client ,   caption: &Rendered, chat: PackedChat )-> Result<i32>{
     let random_id =  generate_random_id();
 
-
-   let msg =  client.invoke(&SendMessage {
+    let updates = send::invoke(client, chat.id, &SendMessage {
         no_webpage: false,
         silent: message.silent(),
         background: false,
         clear_draft: true,
-        peer: chan,
+        peer: chat.to_input_peer(),
         reply_to: None,
-        message: caption.unwrap_or("no caption".to_string()), //replace with own text??
-       random_id ,
+        message: caption.text.clone(),
+        random_id,
         reply_markup: message.reply_markup().clone(),
-        entities:None,
+        entities: Some(caption.sendable_entities()),
         schedule_date: None,
         send_as: None,
         noforwards: false,
         update_stickersets_order: false,
         invert_media: false,
-       quick_reply_shortcut: None,
-       effect: None,
-   })
+        quick_reply_shortcut: None,
+        effect: None,
+    })
         .await?;
 
-Ok(())
+    // Not an error, failing the job now would copy the message a second time.
+    Ok(sent_message_id(&updates, random_id).unwrap_or_else(|| {
+        warn!("Telegram did not report the id of the copy of message {}", message.id());
+        0
+    }))
+}
+
+/// Finds the id Telegram gave the message sent with `random_id`.
+fn sent_message_id(updates: &enums::Updates, random_id: i64) -> Option<i32> {
+    let updates = match updates {
+        enums::Updates::UpdateShortSentMessage(sent) => return Some(sent.id),
+        enums::Updates::Updates(types::Updates { updates, .. }) => updates,
+        enums::Updates::Combined(types::UpdatesCombined { updates, .. }) => updates,
+        _ => return None,
+    };
 
-//Ok(())
+    updates.iter().find_map(|update| match update {
+        enums::Update::MessageId(update) if update.random_id == random_id => Some(update.id),
+        _ => None,
+    })
+}
+
+/// Looks up a chat among the dialogs to get hold of its access hash.
+async fn resolve_chat(client: &Client, chat_id: i64) -> Result<PackedChat> {
+    let mut dialogs = client.iter_dialogs();
+    while let Some(dialog) = dialogs.next().await? {
+        if dialog.chat().id() == chat_id {
+            return Ok(dialog.chat().pack());
+        }
+    }
+    Err(anyhow!("Chat {chat_id} is not among the dialogs"))
 }
 
 
@@ -335,9 +359,8 @@ static LAST_ID: AtomicI64 = AtomicI64::new(0);
             .expect("system time is before epoch")
             .as_nanos() as i64;
 
-        LAST_ID
-            .compare_exchange(0, now, Ordering::SeqCst, Ordering::SeqCst)
-            .unwrap();
+        // Fails if another lane seeded the counter first, which is just as good.
+        let _ = LAST_ID.compare_exchange(0, now, Ordering::SeqCst, Ordering::SeqCst);
     }
 
     LAST_ID.fetch_add(1, Ordering::SeqCst)
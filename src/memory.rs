@@ -0,0 +1,348 @@
+use std::borrow::Cow;
+
+use anyhow::{bail, Result};
+use grammers_client::Client;
+use grammers_client::types::Message;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::{info, warn};
+
+use crate::config::TranslationConfig;
+use crate::db::{Job, MemoryEntry};
+use crate::formatting::strip_footer;
+use crate::lang::{find_language_by_channel, LangKey, Language};
+use crate::{outbox, translation};
+
+/// Translates `text` paragraph by paragraph, taking every paragraph the translation memory
+/// knows from there and sending only the rest to the translation engines.
+/// The memory only holds German sources, posts in other languages go to the engines as a whole.
+/// Fuzzy matches are only taken if they carry the same numbers, the engines translate the rest.
+pub async fn translate(text: &str, source_lang: &LangKey, lang: &Language, db_pool: &PgPool, config: &TranslationConfig) -> Result<String> {
+    if *source_lang != LangKey::DE {
+        return translation::translate(text, source_lang, &lang.lang_key, &lang.lang_key_deepl, config).await;
+    }
+
+    let lang_code = lang.lang_key.code();
+    let paragraphs = text.split('\n').collect::<Vec<&str>>();
+
+    let mut translated = Vec::with_capacity(paragraphs.len());
+    for paragraph in &paragraphs {
+        translated.push(match paragraph.trim() {
+            "" => Some(String::new()),
+            paragraph => MemoryEntry::lookup(&lang_code, paragraph, db_pool).await?
+                .filter(|entry| numbers(&entry.source) == numbers(paragraph))
+                .map(|entry| entry.target),
+        });
+    }
+
+    let hits = translated.iter().zip(&paragraphs).filter(|(t, p)| t.is_some() && !p.trim().is_empty()).count();
+    if hits == 0 {
+        return translation::translate(text, source_lang, &lang.lang_key, &lang.lang_key_deepl, config).await;
+    }
+
+    let missing = (0..paragraphs.len()).filter(|&i| translated[i].is_none()).collect::<Vec<usize>>();
+    info!("Translation memory matched {hits} paragraphs, {} left for the engines", missing.len());
+
+    if !missing.is_empty() {
+        let rest = missing.iter().map(|&i| paragraphs[i]).collect::<Vec<&str>>().join("\n");
+        let rest = translation::translate(&rest, source_lang, &lang.lang_key, &lang.lang_key_deepl, config).await?;
+        let rest = rest.split('\n').collect::<Vec<&str>>();
+
+        if rest.len() != missing.len() {
+            warn!("Engine changed the paragraph count, translating without the translation memory");
+            return translation::translate(text, source_lang, &lang.lang_key, &lang.lang_key_deepl, config).await;
+        }
+
+        for (i, paragraph) in missing.into_iter().zip(rest) {
+            translated[i] = Some(paragraph.to_string());
+        }
+    }
+
+    Ok(translated.into_iter().flatten().collect::<Vec<String>>().join("\n"))
+}
+
+/// The digit runs of `text` in order, a fuzzy match with other numbers would publish the wrong casualties or dates.
+fn numbers(text: &str) -> Vec<&str> {
+    text.split(|c: char| !c.is_ascii_digit())
+        .filter(|run| !run.is_empty())
+        .collect()
+}
+
+/// Pairs up source and corrected paragraphs, or the whole texts if their paragraphs don't line up.
+fn align<'a>(source: &'a str, corrected: &'a str) -> Vec<(&'a str, &'a str)> {
+    let non_empty = |text: &'a str| text.split('\n')
+        .map(str::trim)
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect::<Vec<&str>>();
+
+    let (source_paragraphs, corrected_paragraphs) = (non_empty(source), non_empty(corrected));
+    if source_paragraphs.len() == corrected_paragraphs.len() {
+        source_paragraphs.into_iter().zip(corrected_paragraphs).collect()
+    } else {
+        vec![(source.trim(), corrected.trim())]
+    }
+}
+
+/// Stores the segments of a human correction, `corrected` may still carry the footer.
+pub async fn learn(source: &str, corrected: &str, lang: &Language, db_pool: &PgPool) -> Result<usize> {
+    let pairs = align(source, strip_footer(corrected, lang));
+
+    for (source, target) in &pairs {
+        MemoryEntry {
+            lang: lang.lang_key.code(),
+            source: source.to_string(),
+            target: target.to_string(),
+        }.upsert(db_pool).await?;
+    }
+
+    info!("Learned {} segments for {}", pairs.len(), lang.lang_key);
+    Ok(pairs.len())
+}
+
+/// Learns from an editor changing a mirrored post directly in a language channel,
+/// without the prefix `outbox::deliver` put in front of it.
+pub async fn learn_from_edit(message: &Message, client: &Client, db_pool: &PgPool) -> Result<()> {
+    let Some(lang) = find_language_by_channel(message.chat().id()) else {
+        return Ok(());
+    };
+
+    let Some(job) = Job::find_by_post(&lang.lang_key.code(), message.id(), db_pool).await? else {
+        return Ok(());
+    };
+
+    let source = outbox::fetch_source(&job, outbox::source_chat_of(&job)?, client).await?;
+    let edited = message.html_text();
+    let edited = edited.strip_prefix(outbox::CHANNEL_PREFIX).unwrap_or(&edited);
+    learn(&outbox::source_of(&source).to_html(), edited, lang, db_pool).await?;
+    Ok(())
+}
+
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename = "tmx")]
+struct Tmx {
+    #[serde(rename = "@version")]
+    version: String,
+    header: TmxHeader,
+    body: TmxBody,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct TmxHeader {
+    #[serde(rename = "@creationtool")]
+    creation_tool: String,
+    #[serde(rename = "@creationtoolversion")]
+    creation_tool_version: String,
+    #[serde(rename = "@segtype")]
+    seg_type: String,
+    #[serde(rename = "@o-tmf")]
+    o_tmf: String,
+    #[serde(rename = "@adminlang")]
+    admin_lang: String,
+    #[serde(rename = "@srclang")]
+    src_lang: String,
+    #[serde(rename = "@datatype")]
+    data_type: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct TmxBody {
+    #[serde(rename = "tu", default)]
+    units: Vec<TranslationUnit>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct TranslationUnit {
+    #[serde(rename = "tuv")]
+    variants: Vec<TranslationUnitVariant>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct TranslationUnitVariant {
+    /// quick-xml drops the `xml` namespace prefix when deserializing.
+    #[serde(rename = "@xml:lang", alias = "@lang")]
+    lang: String,
+    seg: String,
+}
+
+fn source_lang() -> String {
+    LangKey::DE.code()
+}
+
+/// Reduces a language tag like `de-DE` to its lowercased primary subtag.
+fn primary_subtag(lang: &str) -> String {
+    lang.split(['-', '_']).next().unwrap_or(lang).to_lowercase()
+}
+
+fn to_tmx(entries: &[MemoryEntry]) -> Result<String> {
+    let tmx = Tmx {
+        version: "1.4".to_string(),
+        header: TmxHeader {
+            creation_tool: env!("CARGO_PKG_NAME").to_string(),
+            creation_tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            seg_type: "paragraph".to_string(),
+            o_tmf: "mn-rs".to_string(),
+            admin_lang: "en".to_string(),
+            src_lang: source_lang(),
+            data_type: "html".to_string(),
+        },
+        body: TmxBody {
+            units: entries.iter().map(|entry| TranslationUnit {
+                variants: vec![
+                    TranslationUnitVariant { lang: source_lang(), seg: entry.source.clone() },
+                    TranslationUnitVariant { lang: entry.lang.clone(), seg: entry.target.clone() },
+                ],
+            }).collect(),
+        },
+    };
+
+    Ok(quick_xml::se::to_string(&tmx)?)
+}
+
+lazy_static! {
+    static ref SEG_REGEX: Regex = Regex::new(r"(?s)(<seg\b[^>]*>)(.*?)(</seg>)").expect("Invalid regex pattern");
+    /// Inline elements CAT tools put into segments, `bpt`, `ept`, `ph`, `it` and `ut` hold the original markup,
+    /// `hi` and `sub` hold text.
+    static ref INLINE_REGEX: Regex = Regex::new(r"</?(?:bpt|ept|ph|it|ut|hi|sub)\b[^>]*>").expect("Invalid regex pattern");
+}
+
+/// Drops the inline elements of every segment but keeps their content, which turns the segment back into
+/// the escaped HTML it was made from. quick-xml can't read mixed content without trimming the text around it.
+fn flatten_segments(xml: &str) -> Cow<'_, str> {
+    SEG_REGEX.replace_all(xml, |seg: &Captures| {
+        format!("{}{}{}", &seg[1], INLINE_REGEX.replace_all(&seg[2], ""), &seg[3])
+    })
+}
+
+/// Reads every source/target pair of a TMX document, units without a source variant are skipped.
+/// Only German sources are accepted, as the memory is only consulted for those.
+fn from_tmx(xml: &str) -> Result<Vec<MemoryEntry>> {
+    let tmx: Tmx = quick_xml::de::from_str(&flatten_segments(xml))?;
+    let src_lang = primary_subtag(&tmx.header.src_lang);
+    if src_lang != source_lang() {
+        bail!("Translation memories need `{}` as source language, not `{}`", source_lang(), tmx.header.src_lang);
+    }
+
+    let mut entries = Vec::new();
+    for unit in tmx.body.units {
+        let (source, targets): (Vec<_>, Vec<_>) = unit.variants.into_iter()
+            .partition(|variant| primary_subtag(&variant.lang) == src_lang);
+        let Some(source) = source.into_iter().next() else {
+            continue;
+        };
+
+        entries.extend(targets.into_iter().map(|target| MemoryEntry {
+            lang: primary_subtag(&target.lang),
+            source: source.seg.clone(),
+            target: target.seg,
+        }));
+    }
+
+    Ok(entries)
+}
+
+pub async fn export_tmx(path: &str, db_pool: &PgPool) -> Result<()> {
+    let entries = MemoryEntry::all(db_pool).await?;
+    std::fs::write(path, to_tmx(&entries)?)?;
+    info!("Exported {} translation memory entries to {path}", entries.len());
+    Ok(())
+}
+
+pub async fn import_tmx(path: &str, db_pool: &PgPool) -> Result<()> {
+    let entries = from_tmx(&std::fs::read_to_string(path)?)?;
+    for entry in &entries {
+        entry.upsert(db_pool).await?;
+    }
+    info!("Imported {} translation memory entries from {path}", entries.len());
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_align_matching_paragraphs() {
+        // Arrange
+        let source = "Erster Absatz\n\nZweiter Absatz";
+        let corrected = "First paragraph\n\nSecond paragraph";
+
+        // Act
+        let pairs = align(source, corrected);
+
+        // Assert
+        assert_eq!(pairs, vec![("Erster Absatz", "First paragraph"), ("Zweiter Absatz", "Second paragraph")]);
+    }
+
+    #[test]
+    fn test_align_falls_back_to_whole_text() {
+        let pairs = align("Ein Absatz", "One\nparagraph");
+        assert_eq!(pairs, vec![("Ein Absatz", "One\nparagraph")]);
+    }
+
+    #[test]
+    fn test_tmx_round_trip() {
+        // Arrange
+        let entries = vec![MemoryEntry {
+            lang: "en".to_string(),
+            source: "Kiew <b>meldet</b>".to_string(),
+            target: "Kyiv <b>reports</b>".to_string(),
+        }];
+
+        // Act
+        let xml = to_tmx(&entries).unwrap();
+        let imported = from_tmx(&xml).unwrap();
+
+        // Assert
+        assert_eq!(imported, entries);
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(numbers("Am 24.02. fielen 3 Raketen"), vec!["24", "02", "3"]);
+        assert_ne!(numbers("12 Verletzte in Charkiw"), numbers("13 Verletzte in Charkiw"));
+    }
+
+    #[test]
+    fn test_tmx_region_subtags() {
+        // Arrange
+        let xml = r#"<tmx version="1.4"><header creationtool="x" creationtoolversion="1" segtype="sentence" o-tmf="x" adminlang="en" srclang="de-DE" datatype="plaintext"/>
+            <body><tu><tuv xml:lang="de-DE"><seg>Hallo</seg></tuv><tuv xml:lang="en-GB"><seg>Hello</seg></tuv></tu></body></tmx>"#;
+
+        // Act
+        let imported = from_tmx(xml).unwrap();
+
+        // Assert
+        assert_eq!(imported, vec![MemoryEntry { lang: "en".to_string(), source: "Hallo".to_string(), target: "Hello".to_string() }]);
+    }
+
+    #[test]
+    fn test_tmx_inline_markup() {
+        // Arrange
+        let xml = r#"<tmx version="1.4"><header creationtool="x" creationtoolversion="1" segtype="sentence" o-tmf="x" adminlang="en" srclang="de" datatype="html"/>
+            <body><tu>
+                <tuv xml:lang="de"><seg>Kiew <bpt i="1">&lt;b&gt;</bpt>meldet<ept i="1">&lt;/b&gt;</ept> Angriffe<ph x="2"/></seg></tuv>
+                <tuv xml:lang="en"><seg>Kyiv <bpt i="1">&lt;b&gt;</bpt>reports<ept i="1">&lt;/b&gt;</ept> <hi type="em">attacks</hi></seg></tuv>
+            </tu></body></tmx>"#;
+
+        // Act
+        let imported = from_tmx(xml).unwrap();
+
+        // Assert
+        assert_eq!(imported, vec![MemoryEntry {
+            lang: "en".to_string(),
+            source: "Kiew <b>meldet</b> Angriffe".to_string(),
+            target: "Kyiv <b>reports</b> attacks".to_string(),
+        }]);
+    }
+
+    #[test]
+    fn test_tmx_rejects_other_source_languages() {
+        let xml = r#"<tmx version="1.4"><header creationtool="x" creationtoolversion="1" segtype="sentence" o-tmf="x" adminlang="en" srclang="en-US" datatype="plaintext"/>
+            <body><tu><tuv xml:lang="en-US"><seg>Hello</seg></tuv><tuv xml:lang="de"><seg>Hallo</seg></tuv></tu></body></tmx>"#;
+        assert!(from_tmx(xml).is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use grammers_client::Client;
+use grammers_client::types::Message;
+use grammers_session::PackedChat;
+use lazy_static::lazy_static;
+use sqlx::PgPool;
+use tokio::sync::Notify;
+use tokio::time::{sleep, timeout};
+use tracing::{error, info, warn};
+
+use crate::db::{Job, JobState, Post};
+use crate::entities::{self, Rendered};
+use crate::formatting::{add_footer, repair_html, strip_footer, strip_html};
+use crate::lang::{find_language, Language};
+use crate::config::Config;
+use crate::routing::FooterPolicy;
+use crate::{detect, handle_error, memory, peers, send, shadow, shutdown, tags, translation};
+
+/// Put in front of the translations sent to the language channels and the log group.
+pub const CHANNEL_PREFIX: &str = "TRANS LANG ";
+const PACK_PREFIX: &str = "TRANS PACK ";
+
+/// The worker polls at least this often, even if nobody notified it about new jobs.
+const IDLE_POLL: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    static ref JOBS_AVAILABLE: Notify = Notify::new();
+    /// Tells the jobs claimed by this process apart from those of a daemon or backfill running next to it.
+    static ref WORKER_ID: String = format!("{}-{}", std::process::id(), chrono::Utc::now().timestamp_millis());
+}
+
+/// Persists one job per target language of the post's route, skipping the language it is written in
+/// and those its control tags rule out.
+pub async fn enqueue(message: &Message, db_pool: &PgPool, config: &Config) -> Result<()> {
+    let route = config.route_for(message.chat().id())
+        .ok_or_else(|| anyhow!("No route for source chat {}", message.chat().id()))?;
+    let targeting = tags::targeting(message.text());
+    let source_lang = detect::source_language(route.source_lang, &source_of(message).text);
+    let langs = route.targets.iter()
+        .filter(|lang| lang.lang_key != source_lang)
+        .map(|lang| lang.lang_key.code())
+        .filter(|code| targeting.allows(code))
+        .collect::<Vec<String>>();
+
+    if langs.is_empty() {
+        info!("Source message {} has no target languages, not mirroring it", message.id());
+        return Ok(());
+    }
+
+    enqueue_for(message, &langs, db_pool).await?;
+    Ok(())
+}
+
+/// The source post without its control tags, as the translation memory knows it.
+pub fn source_of(message: &Message) -> Rendered {
+    tags::strip(&Rendered {
+        text: message.text().to_string(),
+        entities: message.fmt_entities().cloned().unwrap_or_default(),
+    })
+}
+
+/// Returns the ids of the new jobs, posts queued before keep their existing ones.
+pub async fn enqueue_for(message: &Message, langs: &[String], db_pool: &PgPool) -> Result<Vec<i32>> {
+    let job_ids = Job::enqueue(&message.chat().pack().to_bytes(), message.id(), langs, db_pool).await?;
+    JOBS_AVAILABLE.notify_one();
+    Ok(job_ids)
+}
+
+/// Requeues all dead-lettered jobs and returns how many there were.
+pub async fn retry_failed(db_pool: &PgPool) -> Result<u64> {
+    let count = Job::retry_failed(db_pool).await?;
+    JOBS_AVAILABLE.notify_one();
+    Ok(count)
+}
+
+/// Moves the jobs of this process an expired shutdown deadline interrupted back into the queue.
+/// Jobs of a crashed process are claimed again once their lease expired.
+pub async fn requeue_interrupted(db_pool: &PgPool) {
+    match Job::reset_interrupted(&WORKER_ID, db_pool).await {
+        Ok(0) => {}
+        Ok(count) => info!("Requeued {count} interrupted jobs"),
+        Err(e) => error!("Failed to reset interrupted jobs: {e:?}"),
+    }
+}
+
+/// Runs the queued jobs one by one, finishing the current one when a shutdown is requested.
+pub async fn run_worker(client: Client, db_pool: PgPool, config: Config) {
+    while !shutdown::is_requested() {
+        match Job::claim_next(&WORKER_ID, None, &db_pool).await {
+            Ok(Some(job)) => run_job(job, &client, &db_pool, &config).await,
+            Ok(None) => {
+                tokio::select! {
+                    _ = timeout(IDLE_POLL, JOBS_AVAILABLE.notified()) => {}
+                    _ = shutdown::requested() => {}
+                }
+            }
+            Err(e) => {
+                error!("Failed to claim next job: {e:?}");
+                tokio::select! {
+                    _ = sleep(IDLE_POLL) => {}
+                    _ = shutdown::requested() => {}
+                }
+            }
+        }
+    }
+}
+
+/// Publishes the given jobs until none of them is due anymore or a shutdown was requested.
+/// Jobs waiting for a retry are left to the daemon.
+pub async fn drain(job_ids: &[i32], client: &Client, db_pool: &PgPool, config: &Config) -> Result<()> {
+    while !shutdown::is_requested() {
+        let Some(job) = Job::claim_next(&WORKER_ID, Some(job_ids), db_pool).await? else {
+            break;
+        };
+        run_job(job, client, db_pool, config).await;
+    }
+    Ok(())
+}
+
+async fn run_job(job: Job, client: &Client, db_pool: &PgPool, config: &Config) {
+    let Err(err) = publish_job(&job, client, db_pool, config).await else {
+        return;
+    };
+
+    match job.mark_failed(&format!("{err:#}"), db_pool).await {
+        Ok(JobState::Failed) => {
+            let err = err.context(format!("Job {} ({}) moved to dead-letter queue", job.job_id, job.lang));
+            let _ = handle_error(client, config, err).await.map_err(|e| error!("⚠️ Failed to handle error: {e:?}"));
+        }
+        Ok(_) => warn!("Job {} failed on attempt {}, will retry: {err:?}", job.job_id, job.attempts),
+        Err(e) => error!("Failed to record failure of job {}: {e:?}", job.job_id),
+    }
+}
+
+fn language_of(job: &Job) -> Result<&'static Language> {
+    find_language(&job.lang)
+        .ok_or_else(|| anyhow!("Unknown language `{}` in job {}", job.lang, job.job_id))
+}
+
+pub fn source_chat_of(job: &Job) -> Result<PackedChat> {
+    PackedChat::from_bytes(&job.source_chat)
+        .map_err(|_| anyhow!("Invalid source chat in job {}", job.job_id))
+}
+
+pub async fn fetch_source(job: &Job, source_chat: PackedChat, client: &Client) -> Result<Message> {
+    client.get_messages_by_id(source_chat, &[job.source_msg_id]).await?
+        .pop()
+        .flatten()
+        .ok_or_else(|| anyhow!("Source message {} no longer exists", job.source_msg_id))
+}
+
+async fn publish_job(job: &Job, client: &Client, db_pool: &PgPool, config: &Config) -> Result<()> {
+    let lang = language_of(job)?;
+    let source_chat = source_chat_of(job)?;
+
+    if let Some(msg_id) = Post::find_mirrored(source_chat.id, job.source_msg_id, &job.lang, db_pool).await? {
+        info!("Source message {} was already mirrored to {} as {msg_id}, skipping", job.source_msg_id, job.lang);
+        return job.mark_sent(db_pool).await;
+    }
+
+    let message = fetch_source(job, source_chat, client).await?;
+    let rendered = render(&message, lang, client, db_pool, config).await?;
+
+    if config.needs_review(lang) {
+        let header = format!(
+            "📝 Draft {} for @{}\nReply with /approve, /reject or the corrected text.\n\n",
+            job.job_id, lang.username,
+        );
+        let draft = rendered.prefixed(&header).to_input_message();
+        let draft = peers::with_peer(client, config.log_group, |chat| send::send_message(client, chat, draft.clone())).await?;
+        return job.mark_review(draft.id(), &rendered.to_html(), db_pool).await;
+    }
+
+    let msg_id = deliver(&message, lang, client, &rendered, config).await?;
+    complete(job, source_chat, msg_id, db_pool).await?;
+    echo_to_source(&message, client, &rendered, config).await;
+    Ok(())
+}
+
+/// Publishes a draft an editor approved, using the possibly corrected draft text.
+/// The footer is rebuilt, so editors can't drop or garble it.
+pub async fn publish_draft(job: &Job, client: &Client, db_pool: &PgPool, config: &Config) -> Result<()> {
+    let lang = language_of(job)?;
+    let draft_text = job.draft_text.as_deref()
+        .ok_or_else(|| anyhow!("Job {} has no draft", job.job_id))?;
+
+    let source_chat = source_chat_of(job)?;
+    let message = fetch_source(job, source_chat, client).await?;
+    let footer = config.route_for(message.chat().id()).map_or(FooterPolicy::Append, |route| route.footer);
+    let rendered = Rendered::from_html(&apply_footer(strip_footer(draft_text, lang).to_string(), lang, footer)?);
+    let msg_id = deliver(&message, lang, client, &rendered, config).await?;
+    complete(job, source_chat, msg_id, db_pool).await?;
+    echo_to_source(&message, client, &rendered, config).await;
+    Ok(())
+}
+
+/// Translates the source post, either through HTML which is repaired or degraded to plain text if needed,
+/// or with the original entities anchored in the text.
+async fn render(message: &Message, lang: &Language, client: &Client, db_pool: &PgPool, config: &Config) -> Result<Rendered> {
+    let route = config.route_for(message.chat().id());
+    let source = source_of(message);
+    let source_lang = detect::source_language(route.and_then(|route| route.source_lang), &source.text);
+    let footer = route.map_or(FooterPolicy::Append, |route| route.footer);
+
+    // The memory holds HTML paragraphs, they neither match the anchored text nor survive `restore`.
+    if config.translation.entity_pipeline || entities::has_premium_entities(&source.entities) {
+        let anchored = entities::anchor(&source.text, &source.entities);
+        let translated = translation::translate(&anchored, &source_lang, &lang.lang_key, &lang.lang_key_deepl, &config.translation).await?;
+        let rendered = entities::restore(&translated, &source.entities);
+
+        return Ok(Rendered {
+            text: apply_footer(rendered.text, lang, footer)?,
+            ..rendered
+        });
+    }
+
+    let text = memory::translate(&source.to_html(), &source_lang, lang, db_pool, &config.translation).await?;
+
+    let text = match repair_html(&text) {
+        Ok(text) => text,
+        Err(err) => {
+            let err = anyhow!(err).context(format!("Sending source message {} to {} as plain text", message.id(), lang.lang_key));
+            let _ = handle_error(client, config, err).await.map_err(|e| error!("⚠️ Failed to handle error: {e:?}"));
+            strip_html(&text)
+        }
+    };
+
+    Ok(Rendered::from_html(&apply_footer(text, lang, footer)?))
+}
+
+fn apply_footer(text: String, lang: &Language, footer: FooterPolicy) -> Result<String> {
+    match footer {
+        FooterPolicy::Append => add_footer(text, lang),
+        FooterPolicy::Omit => Ok(text),
+    }
+}
+
+/// Returns the id of the message posted to the language channel, `0` if shadow mode only logged it.
+/// Makes exactly one public send, so a failure before `complete` never leaves a post that a retry repeats.
+async fn deliver(message: &Message, lang: &Language, client: &Client, rendered: &Rendered, config: &Config) -> Result<i32> {
+    let meant_for = format!("@{}", lang.username);
+
+    if message.media().is_some() {
+        let msg_id = peers::with_peer(client, lang.channel_id, |chat| shadow::copy_media(message, client, rendered, chat, &meant_for))
+            .await?;
+        return Ok(msg_id.unwrap_or(0));
+    }
+
+    let pack = rendered.prefixed(PACK_PREFIX).to_input_message();
+    peers::with_peer(client, config.log_group, |chat| send::send_message(client, chat, pack.clone()))
+        .await?;
+
+    let announced = rendered.prefixed(CHANNEL_PREFIX);
+    let msg = peers::with_peer(client, lang.channel_id, |chat| shadow::send_message(client, chat, &meant_for, &announced))
+        .await?;
+
+    Ok(msg.map_or(0, |msg| msg.id()))
+}
+
+/// Records the mirrored post, shadow runs only mark the job as sent as nothing was posted publicly.
+async fn complete(job: &Job, source_chat: PackedChat, msg_id: i32, db_pool: &PgPool) -> Result<()> {
+    match shadow::target() {
+        None => job.complete(source_chat.id, msg_id, db_pool).await,
+        Some(_) => job.mark_sent(db_pool).await,
+    }
+}
+
+/// Sends the translation back to the source chat if its route asks for it. Best effort, as the job is complete
+/// by now and retrying it would post to the channel a second time.
+async fn echo_to_source(message: &Message, client: &Client, rendered: &Rendered, config: &Config) {
+    if !config.route_for(message.chat().id()).is_some_and(|route| route.echo) {
+        return;
+    }
+
+    if let Err(err) = shadow::send_message(client, message.chat().pack(), "source chat", rendered).await {
+        let err = err.context(format!("Sending the translation of source message {} back to the source chat failed", message.id()));
+        let _ = handle_error(client, config, err).await.map_err(|e| error!("⚠️ Failed to handle error: {e:?}"));
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{OnceLock, RwLock};
+
+use anyhow::{anyhow, Result};
+use grammers_client::Client;
+use grammers_mtsender::InvocationError;
+use grammers_session::PackedChat;
+use sqlx::PgPool;
+use tracing::{info, warn};
+
+use crate::config::{Config, ShadowMode};
+use crate::db::Peer;
+use crate::lang::{find_language_by_channel, target_languages};
+use crate::resolve_chat;
+
+/// Bot API ids of supergroups and channels are the bare id with this prefix.
+const BOT_API_PREFIX: i64 = -1_000_000_000_000;
+
+/// Errors meaning the stored access hash of a peer is no longer accepted.
+const INVALID_PEER_ERRORS: [&str; 2] = ["CHANNEL_INVALID", "PEER_ID_INVALID"];
+
+struct Registry {
+    db_pool: PgPool,
+    peers: RwLock<HashMap<i64, PackedChat>>,
+}
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+/// Turns Bot API ids like `-1001382962633` into the bare ids grammers uses.
+fn bare_id(chat_id: i64) -> i64 {
+    if chat_id < BOT_API_PREFIX {
+        BOT_API_PREFIX - chat_id
+    } else {
+        chat_id
+    }
+}
+
+fn registry() -> Result<&'static Registry> {
+    REGISTRY.get().ok_or_else(|| anyhow!("Peer registry is not initialized"))
+}
+
+/// Every chat the bot sends to or reads from.
+pub fn known_chats(config: &Config) -> Vec<i64> {
+    let mut chats = vec![config.log_group];
+    for lang in target_languages() {
+        chats.push(lang.channel_id);
+        chats.extend(lang.chat_id.map(bare_id));
+    }
+    chats.extend(config.sources.iter().map(|route| route.source_chat));
+    if config.shadow.mode == ShadowMode::Chat {
+        chats.push(config.shadow.chat);
+    }
+    chats.sort();
+    chats.dedup();
+    chats
+}
+
+/// Resolves a language channel by its username and everything else from the dialogs.
+async fn resolve(client: &Client, chat_id: i64) -> Result<PackedChat> {
+    if let Some(lang) = find_language_by_channel(chat_id) {
+        if let Some(chat) = client.resolve_username(lang.username).await? {
+            return Ok(chat.pack());
+        }
+        warn!("Username @{} does not resolve, looking for channel {chat_id} among the dialogs", lang.username);
+    }
+    resolve_chat(client, chat_id).await
+}
+
+async fn refresh(client: &Client, chat_id: i64) -> Result<PackedChat> {
+    let registry = registry()?;
+    let chat = resolve(client, chat_id).await?;
+
+    Peer { chat_id, packed: chat.to_bytes() }.upsert(&registry.db_pool).await?;
+    registry.peers.write().unwrap().insert(chat_id, chat);
+    Ok(chat)
+}
+
+/// Loads the stored peers and resolves the known chats missing from them, called once on startup.
+pub async fn init(client: &Client, db_pool: &PgPool, config: &Config) -> Result<()> {
+    let mut peers = HashMap::new();
+    for peer in Peer::all(db_pool).await? {
+        match PackedChat::from_bytes(&peer.packed) {
+            Ok(chat) => { peers.insert(peer.chat_id, chat); }
+            Err(_) => warn!("Ignoring invalid stored peer {}", peer.chat_id),
+        }
+    }
+
+    let _ = REGISTRY.set(Registry { db_pool: db_pool.clone(), peers: RwLock::new(peers) });
+
+    for chat_id in known_chats(config) {
+        if registry()?.peers.read().unwrap().contains_key(&chat_id) {
+            continue;
+        }
+        match refresh(client, chat_id).await {
+            Ok(_) => info!("Resolved chat {chat_id}"),
+            Err(err) => warn!("Could not resolve chat {chat_id}: {err:#}"),
+        }
+    }
+
+    Ok(())
+}
+
+/// The stored peer of `chat_id`, resolved now if that failed on startup.
+pub async fn get(client: &Client, chat_id: i64) -> Result<PackedChat> {
+    let stored = registry()?.peers.read().unwrap().get(&chat_id).copied();
+    match stored {
+        Some(chat) => Ok(chat),
+        None => {
+            info!("Chat {chat_id} was not resolved yet, resolving it");
+            refresh(client, chat_id).await
+        }
+    }
+}
+
+fn is_invalid_peer(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<InvocationError>())
+        .any(|cause| matches!(cause, InvocationError::Rpc(rpc) if INVALID_PEER_ERRORS.contains(&rpc.name.as_str())))
+}
+
+/// Runs `call` with the peer of `chat_id`, resolving it again once if Telegram rejects the stored access hash.
+pub async fn with_peer<T, F, Fut>(client: &Client, chat_id: i64, mut call: F) -> Result<T>
+where
+    F: FnMut(PackedChat) -> Fut,
+    Fut: Future<Output=Result<T>>,
+{
+    match call(get(client, chat_id).await?).await {
+        Err(err) if is_invalid_peer(&err) => {
+            warn!("Stored peer of chat {chat_id} is no longer valid, resolving it again: {err:#}");
+            call(refresh(client, chat_id).await?).await
+        }
+        result => result,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bare_id() {
+        assert_eq!(bare_id(-1001382962633), 1382962633);
+        assert_eq!(bare_id(1547521960), 1547521960);
+    }
+}
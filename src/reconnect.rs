@@ -0,0 +1,127 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::ControlFlow;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use grammers_client::Client;
+use grammers_mtsender::ReconnectionPolicy;
+use tokio::time::{sleep, timeout};
+use tracing::{error, info, warn};
+
+use crate::config::Config;
+use crate::{peers, send, shutdown};
+
+/// Delay before the first reconnection attempt, doubled with every further one.
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(60);
+/// Roughly an hour of attempts at `MAX_DELAY`, after that the process exits and gets restarted.
+const MAX_ATTEMPTS: usize = 60;
+/// How often the supervisor checks whether the connection is back after losing it.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+struct Outage {
+    since: DateTime<Utc>,
+    started: Instant,
+    attempts: usize,
+}
+
+static OUTAGE: Mutex<Option<Outage>> = Mutex::new(None);
+
+/// Capped exponential backoff with jitter, so restarts of several bots don't reconnect in lockstep.
+pub struct Backoff;
+
+impl ReconnectionPolicy for Backoff {
+    fn should_retry(&self, attempts: usize) -> ControlFlow<(), Duration> {
+        let mut outage = OUTAGE.lock().unwrap();
+        let outage = outage.get_or_insert_with(|| {
+            warn!("Lost the connection to Telegram");
+            Outage { since: Utc::now(), started: Instant::now(), attempts: 0 }
+        });
+        outage.attempts += 1;
+
+        if attempts >= MAX_ATTEMPTS {
+            error!("Giving up on reconnecting to Telegram after {attempts} attempts");
+            return ControlFlow::Break(());
+        }
+
+        let delay = delay(attempts, random_fraction());
+        warn!("Reconnecting to Telegram in {delay:?}");
+        ControlFlow::Continue(delay)
+    }
+}
+
+/// Between half and all of the capped exponential delay, `jitter` being in `0.0..=1.0`.
+fn delay(attempts: usize, jitter: f64) -> Duration {
+    let exponential = BASE_DELAY.saturating_mul(2u32.saturating_pow(u32::try_from(attempts).unwrap_or(u32::MAX)));
+    exponential.min(MAX_DELAY).mul_f64(0.5 + jitter / 2.0)
+}
+
+/// Good enough for jitter without pulling in a random number generator.
+fn random_fraction() -> f64 {
+    RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64
+}
+
+fn format_offline(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m {s}s"),
+        (h, m, s) => format!("{h}h {m}m {s}s"),
+    }
+}
+
+async fn is_connected(client: &Client) -> bool {
+    timeout(CHECK_INTERVAL, client.get_me()).await.is_ok_and(|me| me.is_ok())
+}
+
+/// Reports every outage to the log group once the connection is back.
+pub async fn supervise(client: Client, config: Config) {
+    while !shutdown::is_requested() {
+        tokio::select! {
+            _ = sleep(CHECK_INTERVAL) => {}
+            _ = shutdown::requested() => return,
+        }
+
+        if OUTAGE.lock().unwrap().is_none() || !is_connected(&client).await {
+            continue;
+        }
+        let Some(outage) = OUTAGE.lock().unwrap().take() else {
+            continue;
+        };
+
+        let text = format!(
+            "🔌 Lost the connection to Telegram at {}, back after {} and {} reconnection attempts",
+            outage.since.format("%Y-%m-%d %H:%M:%S UTC"),
+            format_offline(outage.started.elapsed()),
+            outage.attempts,
+        );
+        info!("{text}");
+        if let Err(err) = peers::with_peer(&client, config.log_group, |chat| send::send_message(&client, chat, text.as_str())).await {
+            error!("⚠️ Failed to report the outage: {err:?}");
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_grows_and_is_capped() {
+        assert_eq!(delay(0, 1.0), BASE_DELAY);
+        assert_eq!(delay(3, 1.0), BASE_DELAY * 8);
+        assert_eq!(delay(3, 0.0), BASE_DELAY * 4);
+        assert_eq!(delay(40, 1.0), MAX_DELAY);
+        assert_eq!(delay(usize::MAX, 0.0), MAX_DELAY / 2);
+    }
+
+    #[test]
+    fn test_format_offline() {
+        assert_eq!(format_offline(Duration::from_secs(42)), "42s");
+        assert_eq!(format_offline(Duration::from_secs(125)), "2m 5s");
+        assert_eq!(format_offline(Duration::from_secs(3725)), "1h 2m 5s");
+    }
+}
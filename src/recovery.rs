@@ -0,0 +1,57 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use grammers_client::{Client, Update};
+use grammers_session::PackedChat;
+use sqlx::PgPool;
+use tracing::info;
+
+use crate::db::Job;
+use crate::config::Config;
+use crate::{process_update, shutdown};
+
+/// Feeds source posts that arrived while the bot was offline through `process_update`, oldest first.
+/// Stops early on shutdown, the rest is recovered on the next start.
+pub async fn recover_gap(client: &Client, db_pool: &PgPool, config: &Config) -> Result<()> {
+    let sources = Job::last_sources(db_pool).await?;
+    if sources.is_empty() {
+        info!("Nothing mirrored yet, skipping gap recovery");
+        return Ok(());
+    }
+
+    let oldest = Utc::now() - Duration::hours(config.recovery_max_age_hours);
+
+    for (source_chat, last_msg_id) in sources {
+        let source_chat = PackedChat::from_bytes(&source_chat)
+            .map_err(|_| anyhow!("Invalid source chat stored in jobs"))?;
+
+        if config.route_for(source_chat.id).is_none() {
+            continue;
+        }
+
+        recover_chat(source_chat, last_msg_id, oldest, client, db_pool, config).await?;
+    }
+
+    Ok(())
+}
+
+async fn recover_chat(source_chat: PackedChat, last_msg_id: i32, oldest: DateTime<Utc>, client: &Client, db_pool: &PgPool, config: &Config) -> Result<()> {
+    let mut missing = Vec::new();
+    let mut history = client.iter_messages(source_chat);
+    while let Some(message) = history.next().await? {
+        if message.id() <= last_msg_id || message.date() < oldest {
+            break;
+        }
+        missing.push(message);
+    }
+
+    info!("Recovering {} posts of source chat {} missed since message {last_msg_id}", missing.len(), source_chat.id);
+
+    for message in missing.into_iter().rev() {
+        if shutdown::is_requested() {
+            break;
+        }
+        process_update(Update::NewMessage(message), client, db_pool, config).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use anyhow::Result;
+use grammers_client::Client;
+use grammers_client::types::Message;
+use sqlx::PgPool;
+
+use crate::config::Config;
+use crate::db::Job;
+use crate::lang::find_language;
+use crate::{memory, outbox};
+
+/// Handles an editor's reply to a draft in the log group.
+///
+/// `/approve` publishes the draft, `/reject` drops it and any other text replaces the draft.
+pub async fn handle_reply(message: &Message, client: &Client, db_pool: &PgPool, config: &Config) -> Result<()> {
+    let Some(draft_msg_id) = message.reply_to_message_id() else {
+        return Ok(());
+    };
+
+    let Some(job) = Job::find_by_draft(draft_msg_id, db_pool).await? else {
+        return Ok(());
+    };
+
+    match message.text().trim() {
+        "/approve" => {
+            outbox::publish_draft(&job, client, db_pool, config).await?;
+            message.reply(format!("✅ Draft {} published", job.job_id)).await?;
+        }
+        "/reject" => {
+            job.mark_rejected(db_pool).await?;
+            message.reply(format!("🗑 Draft {} rejected", job.job_id)).await?;
+        }
+        _ => {
+            let corrected = message.html_text();
+            job.update_draft(&corrected, db_pool).await?;
+
+            let source = outbox::fetch_source(&job, outbox::source_chat_of(&job)?, client).await?;
+            if let Some(lang) = find_language(&job.lang) {
+                memory::learn(&outbox::source_of(&source).to_html(), &corrected, lang, db_pool).await?;
+            }
+
+            message.reply(format!("✏️ Draft {} updated, reply /approve to publish it", job.job_id)).await?;
+        }
+    }
+
+    Ok(())
+}
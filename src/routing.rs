@@ -0,0 +1,137 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use crate::lang::{find_language, target_languages, LangKey, Language};
+use crate::SOURCE_CHANNEL;
+
+/// What gets appended to the translations of a route.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FooterPolicy {
+    /// Hashtags for the flags in the post, followed by the footer of the language.
+    #[default]
+    Append,
+    /// The translation is posted as it is, e.g. for partner channels with their own footer.
+    Omit,
+}
+
+/// A route as written in the `sources` of the config file.
+#[derive(Deserialize)]
+pub struct RouteConfig {
+    source_chat: i64,
+    /// Detected per post if missing.
+    source_lang: Option<String>,
+    /// Language codes, all of `LANGUAGES` if missing.
+    targets: Option<Vec<String>>,
+    #[serde(default)]
+    footer: FooterPolicy,
+    #[serde(default)]
+    echo: bool,
+}
+
+/// A source chat and the language channels its posts are mirrored to.
+#[derive(Clone)]
+pub struct Route {
+    pub source_chat: i64,
+    pub source_lang: Option<LangKey>,
+    pub targets: Vec<&'static Language>,
+    pub footer: FooterPolicy,
+    /// Whether the translations are also sent back to the source chat, never wanted for partner chats.
+    pub echo: bool,
+}
+
+impl TryFrom<RouteConfig> for Route {
+    type Error = anyhow::Error;
+
+    fn try_from(config: RouteConfig) -> Result<Self> {
+        let source_lang = config.source_lang
+            .map(|code| LangKey::from_code(&code).ok_or_else(|| anyhow!("Unknown source language `{code}`")))
+            .transpose()?;
+
+        let targets = match config.targets {
+            None => target_languages().iter().collect(),
+            Some(codes) => codes.iter()
+                .map(|code| find_language(code).ok_or_else(|| anyhow!("Unknown target language `{code}`")))
+                .collect::<Result<Vec<_>>>()?,
+        };
+
+        Ok(Self { source_chat: config.source_chat, source_lang, targets, footer: config.footer, echo: config.echo })
+    }
+}
+
+/// The route used without configured sources: our own channel into every language.
+pub fn default_route() -> Route {
+    Route {
+        source_chat: SOURCE_CHANNEL,
+        source_lang: Some(LangKey::DE),
+        targets: target_languages().iter().collect(),
+        footer: FooterPolicy::Append,
+        echo: true,
+    }
+}
+
+/// Validates the configured routes, reporting every invalid or duplicated one.
+pub fn build_routes(configs: Vec<RouteConfig>) -> Result<Vec<Route>, Vec<String>> {
+    let mut routes: Vec<Route> = Vec::with_capacity(configs.len());
+    let mut problems = Vec::new();
+
+    for config in configs {
+        let source_chat = config.source_chat;
+        if routes.iter().any(|other| other.source_chat == source_chat) {
+            problems.push(format!("Source chat {source_chat} has more than one route"));
+            continue;
+        }
+        match Route::try_from(config) {
+            Ok(route) => routes.push(route),
+            Err(err) => problems.push(format!("Invalid route for source chat {source_chat}: {err}")),
+        }
+    }
+
+    if problems.is_empty() { Ok(routes) } else { Err(problems) }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_routes(json: &str) -> Result<Vec<Route>, Vec<String>> {
+        build_routes(serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn test_parse_routes() {
+        // Arrange
+        let json = r#"[
+            {"source_chat": 1391125365, "source_lang": "de"},
+            {"source_chat": 42, "targets": ["en"], "footer": "omit", "echo": true}
+        ]"#;
+
+        // Act
+        let routes = parse_routes(json).unwrap();
+
+        // Assert
+        assert_eq!(routes[0].source_lang, Some(LangKey::DE));
+        assert_eq!(routes[0].targets.len(), target_languages().len());
+        assert_eq!(routes[0].footer, FooterPolicy::Append);
+        assert!(!routes[0].echo);
+        assert_eq!(routes[1].source_lang, None);
+        assert_eq!(routes[1].targets[0].lang_key, LangKey::EN);
+        assert_eq!(routes[1].footer, FooterPolicy::Omit);
+        assert!(routes[1].echo);
+    }
+
+    #[test]
+    fn test_parse_invalid_routes() {
+        // Act
+        let problems = parse_routes(r#"[{"source_chat": 42, "targets": ["xx"]}, {"source_chat": 42}, {"source_chat": 42}]"#)
+            .err()
+            .unwrap();
+
+        // Assert
+        assert_eq!(problems, vec![
+            "Invalid route for source chat 42: Unknown target language `xx`",
+            "Source chat 42 has more than one route",
+        ]);
+    }
+}
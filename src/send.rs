@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use grammers_client::{Client, InputMessage};
+use grammers_client::types::Message;
+use grammers_mtsender::InvocationError;
+use grammers_session::PackedChat;
+use grammers_tl_types::RemoteCall;
+use lazy_static::lazy_static;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Instant};
+use tracing::warn;
+
+/// Telegram allows roughly one message per second into the same chat.
+const BUCKET_CAPACITY: f64 = 3.0;
+const REFILL_PER_SECOND: f64 = 1.0;
+
+/// How often a single request is retried after FLOOD_WAIT / SLOWMODE_WAIT before giving up.
+const MAX_FLOOD_RETRIES: usize = 5;
+
+/// grammers sleeps through short waits itself, only the long ones end up here.
+/// Waits above this fail the call instead of blocking the caller, the outbox retries the job later.
+static MAX_FLOOD_WAIT_SECS: AtomicU64 = AtomicU64::new(60);
+
+lazy_static! {
+    static ref LIMITER: RateLimiter = RateLimiter::default();
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new() -> Self {
+        Self {
+            tokens: BUCKET_CAPACITY,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes one token, returning how long the caller has to wait before it may send.
+    fn take(&mut self, now: Instant) -> Duration {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * REFILL_PER_SECOND).min(BUCKET_CAPACITY);
+        self.last_refill = now;
+        self.tokens -= 1.0;
+
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / REFILL_PER_SECOND)
+        }
+    }
+}
+
+#[derive(Default)]
+struct RateLimiter {
+    buckets: Mutex<HashMap<i64, TokenBucket>>,
+}
+
+impl RateLimiter {
+    async fn acquire(&self, chat_id: i64) {
+        let wait = self.buckets.lock().await
+            .entry(chat_id)
+            .or_insert_with(TokenBucket::new)
+            .take(Instant::now());
+
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
+
+/// Returns the wait time Telegram asked for if the error is a FLOOD_WAIT or SLOWMODE_WAIT.
+fn flood_wait(err: &InvocationError) -> Option<Duration> {
+    match err {
+        InvocationError::Rpc(rpc) if rpc.name == "FLOOD_WAIT" || rpc.name == "SLOWMODE_WAIT" =>
+            Some(Duration::from_secs(rpc.value.unwrap_or(1) as u64)),
+        _ => None,
+    }
+}
+
+pub fn set_max_flood_wait(max_secs: u64) {
+    MAX_FLOOD_WAIT_SECS.store(max_secs, Ordering::Relaxed);
+}
+
+async fn with_retry<T, F, Fut>(chat_id: i64, mut call: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output=Result<T, InvocationError>>,
+{
+    let mut attempts = 0;
+    loop {
+        LIMITER.acquire(chat_id).await;
+
+        match call().await {
+            Ok(result) => return Ok(result),
+            Err(err) => match flood_wait(&err) {
+                Some(wait) if wait.as_secs() > MAX_FLOOD_WAIT_SECS.load(Ordering::Relaxed) => {
+                    return Err(anyhow!(err).context(format!("Flood wait of {}s for chat {chat_id} is too long to wait for", wait.as_secs())));
+                }
+                Some(wait) if attempts < MAX_FLOOD_RETRIES => {
+                    attempts += 1;
+                    warn!("Flood wait of {}s for chat {chat_id}, retry {attempts}/{MAX_FLOOD_RETRIES}", wait.as_secs());
+                    sleep(wait).await;
+                }
+                _ => return Err(err.into()),
+            }
+        }
+    }
+}
+
+/// Rate-limited `Client::send_message` which sleeps and retries on FLOOD_WAIT.
+pub async fn send_message<M: Into<InputMessage>>(client: &Client, chat: PackedChat, message: M) -> Result<Message> {
+    let message = message.into();
+    with_retry(chat.id, || client.send_message(chat, message.clone())).await
+}
+
+/// Rate-limited `Client::invoke` for raw requests targeting `chat_id`.
+pub async fn invoke<R: RemoteCall>(client: &Client, chat_id: i64, request: &R) -> Result<R::Return> {
+    with_retry(chat_id, || client.invoke(request)).await
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_allows_burst_then_throttles() {
+        // Arrange
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new();
+
+        // Act
+        let burst = (0..BUCKET_CAPACITY as usize).map(|_| bucket.take(now)).collect::<Vec<_>>();
+        let throttled = bucket.take(now);
+
+        // Assert
+        assert!(burst.iter().all(Duration::is_zero));
+        assert_eq!(throttled, Duration::from_secs_f64(1.0 / REFILL_PER_SECOND));
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        // Arrange
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new();
+        for _ in 0..BUCKET_CAPACITY as usize {
+            bucket.take(now);
+        }
+
+        // Act
+        let wait = bucket.take(now + Duration::from_secs(1));
+
+        // Assert
+        assert!(wait.is_zero());
+    }
+}
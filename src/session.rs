@@ -0,0 +1,49 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use grammers_client::Client;
+use grammers_session::Session;
+use sqlx::PgPool;
+use tracing::{info, warn};
+
+use crate::db::StoredSession;
+
+/// Where the session lived before it moved into the database, imported once if it's still around.
+const LEGACY_SESSION_FILE: &str = "mn-rs.session";
+
+/// The session carries the update state, which changes all the time.
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The stored session, a new one if there is none yet.
+pub async fn load(db_pool: &PgPool) -> Result<Session> {
+    if let Some(data) = StoredSession::load(db_pool).await? {
+        return Session::load(&data).context("Stored session is invalid");
+    }
+
+    if Path::new(LEGACY_SESSION_FILE).exists() {
+        info!("Importing the session from {LEGACY_SESSION_FILE}");
+        let session = Session::load_file(LEGACY_SESSION_FILE)?;
+        StoredSession::save(&session.save(), db_pool).await?;
+        return Ok(session);
+    }
+
+    Ok(Session::new())
+}
+
+pub async fn save(client: &Client, db_pool: &PgPool) -> Result<()> {
+    StoredSession::save(&client.session().save(), db_pool).await
+}
+
+/// Saves the session every `SAVE_INTERVAL`, so a crash loses little of the update state.
+pub async fn save_periodically(client: Client, db_pool: PgPool) {
+    let mut interval = tokio::time::interval(SAVE_INTERVAL);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        if let Err(err) = save(&client, &db_pool).await {
+            warn!("Saving the session failed: {err:#}");
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::{bail, Result};
+use grammers_client::Client;
+use grammers_client::types::Message;
+use grammers_session::PackedChat;
+use sqlx::PgPool;
+use tokio::sync::OnceCell;
+use tracing::info;
+
+use crate::config::{ShadowConfig, ShadowMode};
+use crate::db::Post;
+use crate::entities::Rendered;
+use crate::{copy_message, peers, send};
+
+/// Where public sends end up while shadow mode is on.
+///
+/// Shadow mode runs as a separate instance with its own `DATABASE_URL`, otherwise it would claim
+/// the jobs of the live instance. It refuses to start on a database that already has mirrored posts
+/// and doesn't record any itself.
+pub enum ShadowTarget {
+    /// The id of a test chat, every message is prefixed with the chat it was meant for.
+    Chat(i64),
+    /// Nothing is sent, the messages are only logged.
+    Log,
+}
+
+static SHADOW: OnceCell<Option<ShadowTarget>> = OnceCell::const_new();
+
+/// Checks that the test chat for `ShadowMode::Chat` can be resolved, it is looked up in the peer registry on every send.
+pub async fn init(client: &Client, config: &ShadowConfig, db_pool: &PgPool) -> Result<()> {
+    let target = match config.mode {
+        ShadowMode::Off => None,
+        ShadowMode::Log => Some(ShadowTarget::Log),
+        ShadowMode::Chat => {
+            peers::get(client, config.chat).await?;
+            Some(ShadowTarget::Chat(config.chat))
+        }
+    };
+
+    if target.is_some() {
+        if Post::any(db_pool).await? {
+            bail!("Shadow mode needs its own DATABASE_URL, this database already has mirrored posts");
+        }
+        info!("🕶 Shadow mode is on, nothing will be posted publicly");
+    }
+
+    let _ = SHADOW.set(target);
+    Ok(())
+}
+
+pub fn target() -> Option<&'static ShadowTarget> {
+    SHADOW.get().and_then(Option::as_ref)
+}
+
+/// Sends `rendered` to `chat`, or to the shadow target while shadow mode is on.
+/// Returns `None` if the message was only logged.
+pub async fn send_message(client: &Client, chat: PackedChat, meant_for: &str, rendered: &Rendered) -> Result<Option<Message>> {
+    match target() {
+        None => Ok(Some(send::send_message(client, chat, rendered.to_input_message()).await?)),
+        Some(ShadowTarget::Chat(shadow_chat)) => {
+            let rendered = rendered.prefixed(&format!("🕶 {meant_for} ({})\n\n", chat.id));
+            let sent = peers::with_peer(client, *shadow_chat, |test_chat| send::send_message(client, test_chat, rendered.to_input_message())).await?;
+            Ok(Some(sent))
+        }
+        Some(ShadowTarget::Log) => {
+            info!(target: "shadow", chat_id = chat.id, meant_for, text = %rendered.text, "would send message");
+            Ok(None)
+        }
+    }
+}
+
+/// Shadow-aware counterpart of `copy_message`, returns `None` if the message was only logged.
+pub async fn copy_media(message: &Message, client: &Client, caption: &Rendered, chat: PackedChat, meant_for: &str) -> Result<Option<i32>> {
+    match target() {
+        None => Ok(Some(copy_message(message, client, caption, chat).await?)),
+        Some(ShadowTarget::Chat(shadow_chat)) => {
+            let caption = caption.prefixed(&format!("🕶 {meant_for} ({})\n\n", chat.id));
+            let msg_id = peers::with_peer(client, *shadow_chat, |test_chat| copy_message(message, client, &caption, test_chat)).await?;
+            Ok(Some(msg_id))
+        }
+        Some(ShadowTarget::Log) => {
+            info!(target: "shadow", chat_id = chat.id, meant_for, caption = %caption.text, source_msg_id = message.id(), "would copy media");
+            Ok(None)
+        }
+    }
+}
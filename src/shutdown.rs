@@ -0,0 +1,34 @@
+use anyhow::Result;
+use lazy_static::lazy_static;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::info;
+
+lazy_static! {
+    static ref SHUTDOWN: watch::Sender<bool> = watch::channel(false).0;
+}
+
+/// Asks every loop to stop taking new work.
+pub fn request() {
+    SHUTDOWN.send_replace(true);
+}
+
+pub fn is_requested() -> bool {
+    *SHUTDOWN.borrow()
+}
+
+/// Resolves once a shutdown was requested.
+pub async fn requested() {
+    let _ = SHUTDOWN.subscribe().wait_for(|&requested| requested).await;
+}
+
+/// Requests the shutdown on SIGTERM or Ctrl+C.
+pub async fn listen() -> Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = terminate.recv() => info!("Received SIGTERM, shutting down"),
+        _ = tokio::signal::ctrl_c() => info!("Received Ctrl+C, shutting down"),
+    }
+    request();
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::entities::Rendered;
+
+/// `#nurDE` (only German), `#only:en,fr` and `#skip:ru`, with the spaces following them.
+/// A tag has to end in a space or the end of the line, so `#only:en,frx` isn't read as `#only:en`.
+/// The code after `#nur` has to be uppercase, so hashtags like `#nurse` aren't taken for tags.
+const TAG_PATTERN: &str = r"(?m)#(?:nur(?P<nur>[A-Z]{2})|only:(?P<only>[a-zA-Z]{2}(?:,[a-zA-Z]{2})*)|skip:(?P<skip>[a-zA-Z]{2}(?:,[a-zA-Z]{2})*))(?:[ \t]+|$)";
+
+lazy_static! {
+    static ref TAG_REGEX: Regex = Regex::new(TAG_PATTERN).expect("Invalid regex pattern");
+}
+
+/// Which languages a source post asked to be mirrored to with its control tags.
+#[derive(Debug, Default, PartialEq)]
+pub struct Targeting {
+    only: Option<Vec<String>>,
+    skip: Vec<String>,
+}
+
+impl Targeting {
+    pub fn allows(&self, code: &str) -> bool {
+        self.only.as_ref().is_none_or(|only| only.iter().any(|lang| lang == code))
+            && !self.skip.iter().any(|lang| lang == code)
+    }
+}
+
+fn codes(list: &str) -> Vec<String> {
+    list.split(',').map(str::to_lowercase).collect()
+}
+
+/// Reads the control tags of a source post. Several `only` tags add up.
+pub fn targeting(text: &str) -> Targeting {
+    let mut targeting = Targeting::default();
+
+    for tag in TAG_REGEX.captures_iter(text) {
+        if let Some(list) = tag.name("nur").or(tag.name("only")) {
+            targeting.only.get_or_insert_with(Vec::new).extend(codes(list.as_str()));
+        }
+        if let Some(list) = tag.name("skip") {
+            targeting.skip.extend(codes(list.as_str()));
+        }
+    }
+
+    targeting
+}
+
+/// Removes the control tags so they don't end up in the translations.
+pub fn strip(source: &Rendered) -> Rendered {
+    let tags = TAG_REGEX.find_iter(&source.text)
+        .map(|tag| tag.range())
+        .collect::<Vec<_>>();
+    source.without(&tags).trim_end()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_targeting_only_and_skip() {
+        // Act
+        let targeting = targeting("Angriff auf Kiew\n\n#only:en,FR #skip:fr");
+
+        // Assert
+        assert!(targeting.allows("en"));
+        assert!(!targeting.allows("fr"));
+        assert!(!targeting.allows("ru"));
+    }
+
+    #[test]
+    fn test_targeting_nur() {
+        let targeting = targeting("Lokale Meldung #nurDE");
+
+        assert!(targeting.allows("de"));
+        assert!(!targeting.allows("en"));
+    }
+
+    #[test]
+    fn test_targeting_without_tags() {
+        assert_eq!(targeting("#Ukraine #onlyfans #nurse"), Targeting::default());
+    }
+
+    #[test]
+    fn test_targeting_ignores_longer_codes() {
+        assert_eq!(targeting("#only:en,frx"), Targeting::default());
+        assert_eq!(targeting("#skip:ru-ua"), Targeting::default());
+    }
+
+    #[test]
+    fn test_strip_tags_keeps_lines() {
+        let source = Rendered { text: "#only:en\nAngriff auf Kiew #skip:ru\nLuftalarm".to_string(), entities: vec![] };
+
+        assert_eq!(strip(&source).text, "\nAngriff auf Kiew \nLuftalarm");
+    }
+
+    #[test]
+    fn test_strip_tags() {
+        let source = Rendered { text: "Angriff auf Kiew\n\n#only:en #skip:ru".to_string(), entities: vec![] };
+
+        assert_eq!(strip(&source).text, "Angriff auf Kiew");
+    }
+}
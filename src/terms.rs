@@ -0,0 +1,164 @@
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::Entry;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use regex::Regex;
+use tracing::info;
+
+use crate::formatting::{split_html, HtmlSegment, ASSETS};
+use crate::lang::LangKey;
+
+/// Up to three trailing letters or a possessive are kept, so inflected forms like `Kiews` or `Kiev's` match too.
+const SUFFIX_PATTERN: &str = r"(?P<suffix>'s|\p{Ll}{0,3})\b";
+
+lazy_static! {
+    static ref TERMS: Mutex<HashMap<String, Vec<Term>>> = Mutex::new(HashMap::new());
+}
+
+/// One spelling the engines produce and the spelling we want instead.
+#[derive(Debug, Clone)]
+pub struct Term {
+    pattern: Regex,
+    preferred: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Substitution {
+    pub found: String,
+    pub replaced: String,
+}
+
+impl Term {
+    pub fn new(variant: &str, preferred: &str) -> Result<Self> {
+        Ok(Self {
+            pattern: Regex::new(&format!(r"\b{}{SUFFIX_PATTERN}", regex::escape(variant)))?,
+            preferred: preferred.to_string(),
+        })
+    }
+}
+
+/// Reads `res/<lang>/terms.json`, longer variants first so they win over their prefixes.
+pub fn load_terms(lang_key: &LangKey) -> Result<Vec<Term>> {
+    let Some(file) = ASSETS.get_file(format!("{}/terms.json", lang_key.code())) else {
+        return Ok(Vec::new());
+    };
+
+    let table: BTreeMap<String, String> = serde_json::from_slice(file.contents())?;
+    let mut variants = table.iter().collect::<Vec<_>>();
+    variants.sort_by_key(|(variant, _)| std::cmp::Reverse(variant.chars().count()));
+
+    variants.into_iter()
+        .map(|(variant, preferred)| Term::new(variant, preferred))
+        .collect()
+}
+
+pub fn normalize(text: &str, terms: &[Term]) -> (String, Vec<Substitution>) {
+    let mut text = text.to_string();
+    let mut substitutions = Vec::new();
+
+    for term in terms {
+        let replaced = term.pattern.replace_all(&text, |captures: &regex::Captures| {
+            let replacement = format!("{}{}", term.preferred, &captures["suffix"]);
+            if replacement != captures[0] {
+                substitutions.push(Substitution {
+                    found: captures[0].to_string(),
+                    replaced: replacement.clone(),
+                });
+            }
+            replacement
+        }).into_owned();
+        text = replaced;
+    }
+
+    (text, substitutions)
+}
+
+/// Normalizes only the text runs, so link targets and other attributes keep their spelling.
+fn normalize_html(html: &str, terms: &[Term]) -> (String, Vec<Substitution>) {
+    let mut text = String::with_capacity(html.len());
+    let mut substitutions = Vec::new();
+
+    for segment in split_html(html) {
+        match segment {
+            HtmlSegment::Tag(tag) => text.push_str(tag),
+            HtmlSegment::Text(run) => {
+                let (normalized, found) = normalize(run, terms);
+                text.push_str(&normalized);
+                substitutions.extend(found);
+            }
+        }
+    }
+
+    (text, substitutions)
+}
+
+/// Applies the term table of `lang_key` to a translation and logs every substitution.
+pub fn normalize_translation(text: String, lang_key: &LangKey) -> Result<String> {
+    let mut cache = TERMS.lock().unwrap();
+    let terms = match cache.entry(lang_key.code()) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => entry.insert(load_terms(lang_key)?),
+    };
+
+    let (text, substitutions) = normalize_html(&text, terms);
+    for substitution in &substitutions {
+        info!(target: "terms", lang = %lang_key, found = %substitution.found, replaced = %substitution.replaced, "normalized term");
+    }
+
+    Ok(text)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms() -> Vec<Term> {
+        vec![
+            Term::new("Kiev", "Kyiv").unwrap(),
+            Term::new("Kharkov", "Kharkiv").unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_normalize_replaces_inflected_variants() {
+        // Arrange
+        let text = "Kiev's air defence was active over Kiev and Kharkov.";
+
+        // Act
+        let (normalized, substitutions) = normalize(text, &terms());
+
+        // Assert
+        assert_eq!(normalized, "Kyiv's air defence was active over Kyiv and Kharkiv.");
+        assert_eq!(substitutions.len(), 3);
+        assert_eq!(substitutions[0], Substitution { found: "Kiev's".to_string(), replaced: "Kyiv's".to_string() });
+    }
+
+    #[test]
+    fn test_normalize_leaves_longer_words_alone() {
+        let (normalized, substitutions) = normalize("The Kievskaya station", &terms());
+
+        assert_eq!(normalized, "The Kievskaya station");
+        assert!(substitutions.is_empty());
+    }
+
+    #[test]
+    fn test_normalize_html_leaves_links_alone() {
+        // Arrange
+        let html = r#"<a href="https://example.com/Kiev">Kiev</a> and Kharkov"#;
+
+        // Act
+        let (normalized, substitutions) = normalize_html(html, &terms());
+
+        // Assert
+        assert_eq!(normalized, r#"<a href="https://example.com/Kiev">Kyiv</a> and Kharkiv"#);
+        assert_eq!(substitutions.len(), 2);
+    }
+
+    #[test]
+    fn test_bundled_term_tables_parse() {
+        assert!(!load_terms(&LangKey::EN).unwrap().is_empty());
+    }
+}
@@ -7,31 +7,57 @@ use deepl::{DeepLApi, TagHandling};
 use reqwest::{Client, Response};
 use serde_json::Value;
 use thiserror::Error;
-use tracing::{debug, error};
+use tracing::debug;
 
-use crate::getenv;
+use crate::{glossary, terms};
+use crate::config::TranslationConfig;
+use crate::formatting::translate_html_runs;
 use crate::lang::{DeeplLang, LangKey};
 
+/// Translates with DeepL, falling back to the other engines, and normalizes place names afterwards.
 pub async fn translate(
     text: &str,
+    source_lang: &LangKey,
     target_lang: &LangKey,
     target_lang_deepl: &Option<DeeplLang>,
+    config: &TranslationConfig,
+) -> Result<String> {
+    let translation = translate_with_engines(text, source_lang, target_lang, target_lang_deepl, config).await?;
+    terms::normalize_translation(translation, target_lang)
+}
+
+async fn translate_with_engines(
+    text: &str,
+    source_lang: &LangKey,
+    target_lang: &LangKey,
+    target_lang_deepl: &Option<DeeplLang>,
+    config: &TranslationConfig,
 ) -> Result<String> {
     if let Some(target_lang_deepl) = target_lang_deepl {
         let mut translation_result: Option<String> = None;
 
-        for i in 0..1 {
-            error!("Trying translation with Deepl Key {i}");
+        for (i, key) in config.deepl_keys.iter().enumerate() {
+            debug!("Trying translation with DeepL key {i}");
+
+            let deepl_translator = DeepLApi::with(key).new();
+
+            let mut request = deepl_translator.translate_text(text, target_lang_deepl.clone());
+            request
+                .source_lang(source_lang.deepl_source())
+                .tag_handling(TagHandling::Html);
+
+            // The glossaries translate from German.
+            if *source_lang == LangKey::DE {
+                if let Some(glossary_id) = glossary::glossary_id(i, target_lang) {
+                    request.glossary_id(glossary_id);
+                }
+            }
 
-            let deepl_translator = DeepLApi::with(getenv!(format!("DEEPL_{i}").to_string()).as_str()).new();
+            let Ok(response) = request.await else {
+                continue;
+            };
 
-            match deepl_translator
-                .translate_text(text, target_lang_deepl.clone())
-                .source_lang(DeeplLang::DE)
-                .tag_handling(TagHandling::Html)
-                .await
-                .unwrap_or(continue )
-            .translations.get(0)
+            match response.translations.get(0)
             {
                 None => continue,
                 Some(translation) => {
@@ -41,21 +67,32 @@ pub async fn translate(
         };
 
         match translation_result {
-            None => translate_alternative(text, target_lang).await,
+            None => translate_alternative(text, source_lang, target_lang, config).await,
             Some(translation_result) => Ok(translation_result)
         }
     } else {
-        translate_alternative(text, target_lang).await
+        translate_alternative(text, source_lang, target_lang, config).await
     }
 }
 
-async fn translate_alternative(text: &str, target_lang: &LangKey) -> Result<String> {
-    Translator::new(LangKey::DE, target_lang)
-        .translate(text)
-        .await?
-        .as_str()
-        .map(str::to_string)
-        .ok_or_else(|| TranslationError::TranslationNotFound.into())
+/// The other engines only handle plain text, so the markup is kept aside and only the text runs are translated.
+async fn translate_alternative(text: &str, source_lang: &LangKey, target_lang: &LangKey, config: &TranslationConfig) -> Result<String> {
+    let translator = Translator {
+        engine: config.fallback_engine.clone(),
+        ..Translator::new(*source_lang, target_lang)
+    };
+
+    translate_html_runs(text, |run| {
+        let translator = &translator;
+        async move {
+            translator
+                .translate(&run)
+                .await?
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| TranslationError::TranslationNotFound.into())
+        }
+    }).await
 }
 
 
@@ -118,8 +155,6 @@ impl Translator {
             return Ok(Value::String(text.into()));
         }
 
-        eprintln!("EN --- {}", self.engine.base_url());
-
         match &self.engine {
             Engine::Deepl { api_key, .. } => {
                 let response: Value = self
@@ -143,8 +178,6 @@ impl Translator {
                     &[("tl", &self.target), ("sl", &self.source), ("q", text)],
                 ).await?;
 
-                println!("GO");
-
                 let html = response.text().await?;
                 let document = scraper::Html::parse_document(&html);
                 let selector = match scraper::Selector::parse("div.result-container") {
@@ -155,7 +188,6 @@ impl Translator {
 
                 if let Some(div) = document.select(&selector).next() {
                     let res = div.text().collect::<String>();
-                    println!("-- {res}");
                     Ok(Value::String(res.trim().to_string()))
                 } else {
                     return Err(TranslationError::TranslationNotFound.into());
@@ -1004,21 +1036,19 @@ impl QcriTrans {
 
 #[cfg(test)]
 mod tests {
-    use std::env;
-    use std::sync::Once;
-
     use tokio::runtime::Runtime;
 
     use crate::lang::LangKey;
 
     use super::*;
 
-    static INIT: Once = Once::new();
+    const DEEPL_KEY: &str = "d717cd13-e042-9301-0cb1-7afb29749bee:fx";
 
-    fn initialize() {
-        INIT.call_once(|| unsafe {
-            env::set_var("DEEPL", "d717cd13-e042-9301-0cb1-7afb29749bee:fx");
-        });
+    fn config(deepl_key: &str) -> TranslationConfig {
+        TranslationConfig {
+            deepl_keys: vec![deepl_key.to_string()],
+            ..TranslationConfig::default()
+        }
     }
 
     // Helper function to create a runtime
@@ -1030,15 +1060,13 @@ mod tests {
     // Happy path test for translate function with DeeplLang provided
     #[test]
     fn test_translate_with_deepl_lang() {
-        initialize();
-
         // Arrange
         let text = "Hallo Welt";
         let target_lang = &LangKey::EN;
         let target_lang_deepl = Some(DeeplLang::EN);
 
         // Act
-        let result = block_on(translate(text, target_lang, &target_lang_deepl));
+        let result = block_on(translate(text, &LangKey::DE, target_lang, &target_lang_deepl, &config(DEEPL_KEY)));
 
         // Assert
         assert!(result.is_ok());
@@ -1048,15 +1076,13 @@ mod tests {
     // Happy path test for translate function without DeeplLang provided
     #[test]
     fn test_translate_without_deepl_lang() {
-        initialize();
-
         // Arrange
         let text = "Hallo Welt";
         let target_lang = &LangKey::EN;
         let target_lang_deepl = None;
 
         // Act
-        let result = block_on(translate(text, target_lang, &target_lang_deepl));
+        let result = block_on(translate(text, &LangKey::DE, target_lang, &target_lang_deepl, &config(DEEPL_KEY)));
 
         // Assert
         assert!(result.is_ok());
@@ -1066,15 +1092,13 @@ mod tests {
     // Edge case test for translate function with empty text
     #[test]
     fn test_translate_empty_text() {
-        initialize();
-
         // Arrange
         let text = "";
         let target_lang = &LangKey::EN;
         let target_lang_deepl = Some(DeeplLang::EN);
 
         // Act
-        let result = block_on(translate(text, target_lang, &target_lang_deepl));
+        let result = block_on(translate(text, &LangKey::DE, target_lang, &target_lang_deepl, &config(DEEPL_KEY)));
 
         // Assert
         assert!(result.is_err());
@@ -1087,18 +1111,16 @@ mod tests {
     // Error case test for translate function with too many requests
     #[test]
     fn test_translate_too_many_requests() {
-        initialize();
-
         // Arrange
         let text = "Hallo Welt";
         let target_lang = &LangKey::EN;
         let target_lang_deepl = Some(DeeplLang::EN);
 
-        // Simulate too many requests by setting the DEEPL environment variable to an invalid key
-        unsafe { env::set_var("DEEPL", "invalid_key"); }
+        // Simulate too many requests with an invalid key
+        let config = config("invalid_key");
 
         // Act
-        let result = block_on(translate(text, target_lang, &target_lang_deepl));
+        let result = block_on(translate(text, &LangKey::DE, target_lang, &target_lang_deepl, &config));
 
         // Assert
         assert!(result.is_err());
@@ -1,21 +1,10 @@
 use std::io;
 use std::io::{BufRead, Write};
+use std::process::{Command, Stdio};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use thiserror::Error;
 
-#[macro_export]
-macro_rules! getenv {
-    ($envvar:expr) => {
-        std::env::var($envvar).expect(format!("should specify `{}` in .env file",$envvar).as_str())
-    };
-    ($envvar:expr, $type:ty) => {
-        getenv!($envvar)
-            .parse::<$type>()
-            .expect(format!( "{} should be of type {}",$envvar, stringify!($type)).as_str())
-    };
-}
-
 
 pub fn prompt(message: &str) -> Result<String> {
     let stdout = io::stdout();
@@ -30,3 +19,17 @@ pub fn prompt(message: &str) -> Result<String> {
     stdin.read_line(&mut line)?;
     Ok(line)
 }
+
+/// Like `prompt`, but with the terminal echo turned off, for passwords.
+pub fn prompt_hidden(message: &str) -> Result<String> {
+    let echo_off = Command::new("stty").arg("-echo").stdin(Stdio::inherit()).status()?;
+    if !echo_off.success() {
+        bail!("Turning off the terminal echo failed");
+    }
+
+    let line = prompt(message);
+
+    Command::new("stty").arg("echo").stdin(Stdio::inherit()).status()?;
+    println!();
+    line
+}